@@ -0,0 +1,374 @@
+use std::fmt::Write;
+use std::io::IsTerminal;
+use std::ops::Range;
+use crate::utils::{expand_tabs, json_string};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const GUTTER: &str = "\x1b[1;34m";
+
+// Severity is how serious a diagnostic is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+    Help,
+}
+
+impl Severity {
+    /// name returns the label printed in front of the message, e.g. "error"
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note",
+            Severity::Help => "help",
+        }
+    }
+
+    /// color returns the ANSI escape sequence used for the severity
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+            Severity::Note => "\x1b[1;32m",
+            Severity::Help => "\x1b[1;36m",
+        }
+    }
+}
+
+// ErrorFormat selects how diagnostics are written out
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum ErrorFormat {
+    #[default]
+    Human, // rendered with source snippets for people
+    Json,  // one JSON object per line for editors and graders
+}
+
+impl std::str::FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(ErrorFormat::Human),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("unknown error format '{s}', expected 'human' or 'json'")),
+        }
+    }
+}
+
+// Label points at a byte range of the source. The primary label marks where the problem
+// is, secondary labels point at related code
+#[derive(Debug, PartialEq, Clone)]
+pub struct Label {
+    pub span: Range<usize>,
+    pub message: String,
+    pub primary: bool,
+}
+
+// Suggestion is a machine-applicable fix: replace the span with the replacement text
+#[derive(Debug, PartialEq, Clone)]
+pub struct Suggestion {
+    pub span: Range<usize>,
+    pub replacement: String,
+    pub message: String,
+}
+
+impl Suggestion {
+    /// apply returns the source with the suggestion applied
+    pub fn apply(&self, source: &str) -> String {
+        let mut fixed = source.to_string();
+        fixed.replace_range(self.span.clone(), &self.replacement);
+        fixed
+    }
+
+    /// fixed_line returns the text after applying the suggestion from the start of the line from is on,
+    /// usually the start of the flagged code, to the end of the line the suggestion is on
+    fn fixed_line(&self, source: &str, from: usize) -> String {
+        let from = from.min(self.span.start);
+        let line_start = source[..from].rfind('\n').map_or(0, |i| i + 1);
+        let fixed = self.apply(source);
+        let replaced_end = self.span.start + self.replacement.len();
+        let line_end = fixed[replaced_end..].find('\n').map_or(fixed.len(), |i| replaced_end + i);
+        fixed[line_start..line_end].to_string()
+    }
+}
+
+// Diagnostic is a message about the source shared by every stage that reports problems
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>, // stable identifier of the kind of problem, e.g. "E0001"
+    pub message: String,
+    pub labels: Vec<Label>,
+    pub notes: Vec<(Severity, String)>, // trailing "= note: ..." and "= help: ..." lines
+    pub suggestions: Vec<Suggestion>,
+}
+
+impl Diagnostic {
+    pub fn new<T: ToString>(severity: Severity, message: T) -> Self {
+        Self {
+            severity,
+            code: None,
+            message: message.to_string(),
+            labels: Vec::new(),
+            notes: Vec::new(),
+            suggestions: Vec::new(),
+        }
+    }
+
+    pub fn error<T: ToString>(message: T) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning<T: ToString>(message: T) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    /// with_code sets the code identifying the kind of problem
+    pub fn with_code(mut self, code: &'static str) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    /// with_label adds the primary label pointing at the problem
+    pub fn with_label<T: ToString>(mut self, span: Range<usize>, message: T) -> Self {
+        self.labels.push(Label { span, message: message.to_string(), primary: true });
+        self
+    }
+
+    /// with_secondary_label adds a label pointing at related code
+    pub fn with_secondary_label<T: ToString>(mut self, span: Range<usize>, message: T) -> Self {
+        self.labels.push(Label { span, message: message.to_string(), primary: false });
+        self
+    }
+
+    /// with_note adds a trailing note line
+    pub fn with_note<T: ToString>(mut self, message: T) -> Self {
+        self.notes.push((Severity::Note, message.to_string()));
+        self
+    }
+
+    /// with_help adds a trailing help line
+    pub fn with_help<T: ToString>(mut self, message: T) -> Self {
+        self.notes.push((Severity::Help, message.to_string()));
+        self
+    }
+
+    /// with_suggestion attaches a fix that replaces the span with the replacement text
+    pub fn with_suggestion<T: ToString, U: ToString>(mut self, span: Range<usize>, replacement: T, message: U) -> Self {
+        self.suggestions.push(Suggestion {
+            span,
+            replacement: replacement.to_string(),
+            message: message.to_string(),
+        });
+        self
+    }
+
+    /// render formats the diagnostic against the source it was reported on, with ANSI colors if color is set.
+    /// Tabs are expanded to tab_width columns, which should match the lexer's so the columns agree
+    pub fn render(&self, source: &str, file_name: &str, color: bool, tab_width: usize) -> String {
+        let paint = |style: &str, text: &str| match color {
+            true => format!("{style}{text}{RESET}"),
+            false => text.to_string(),
+        };
+
+        let mut out = String::new();
+        let heading = match self.code {
+            Some(code) => format!("{}[{code}]", self.severity.name()),
+            None => self.severity.name().to_string(),
+        };
+        let _ = writeln!(out, "{}{}", paint(self.severity.color(), &heading), paint(BOLD, &format!(": {}", self.message)));
+
+        // resolve every label to its line and columns, ordered by where they appear
+        let mut labels: Vec<_> = self.labels.iter().map(|l| (line_col(source, l.span.start), l)).collect();
+        labels.sort_by_key(|((line, col), label)| (*line, *col, !label.primary));
+
+        let width = labels.iter().map(|((line, _), _)| line.to_string().len()).max().unwrap_or(0);
+        let pad = " ".repeat(width);
+        let bar = paint(GUTTER, "|");
+
+        if let Some(((line, col), _)) = labels.iter().find(|(_, l)| l.primary).or(labels.first()) {
+            let _ = writeln!(out, "{pad}{} {file_name}:{line}:{col}", paint(GUTTER, "-->"));
+            let _ = writeln!(out, "{pad} {bar}");
+        }
+
+        let mut previous_line = None;
+        for ((line, col), label) in &labels {
+            let text = source.lines().nth(line - 1).unwrap_or("");
+            // tabs are expanded so the carets below line up whatever the terminal's tab stops are
+            if previous_line != Some(*line) {
+                let _ = writeln!(out, "{} {bar} {}", paint(GUTTER, &format!("{line:>width$}")), expand_tabs(text, tab_width));
+                previous_line = Some(*line);
+            }
+
+            // underline the span, stopping at the end of the line for spans that run past it
+            let (before, after) = text.split_at(text.char_indices().nth(col - 1).map_or(text.len(), |(i, _)| i));
+            let start = expand_tabs(before, tab_width).chars().count();
+            let span_text = source.get(label.span.clone()).unwrap_or("");
+            let span_text = &span_text[..span_text.len().min(after.len())];
+            let len = expand_tabs(&format!("{before}{span_text}"), tab_width).chars().count() - start;
+            let len = len.max(1);
+            let (marker, style) = match label.primary {
+                true => ("^", self.severity.color()),
+                false => ("-", GUTTER),
+            };
+            let underline = paint(style, &format!("{} {}", marker.repeat(len), label.message));
+            let _ = writeln!(out, "{pad} {bar} {}{}", " ".repeat(start), underline.trim_end());
+        }
+
+        for (severity, note) in &self.notes {
+            let _ = writeln!(out, "{pad} {} {}: {note}", paint(GUTTER, "="), paint(BOLD, severity.name()));
+        }
+        for suggestion in &self.suggestions {
+            let from = self.labels.iter().find(|l| l.primary).map_or(suggestion.span.start, |l| l.span.start);
+            let fixed = suggestion.fixed_line(source, from);
+            let _ = writeln!(out, "{pad} {} {}: {}: `{}`", paint(GUTTER, "="), paint(BOLD, "help"), suggestion.message, fixed.trim());
+        }
+        out
+    }
+
+    /// to_json formats the diagnostic as a single line JSON object, counting display columns with tab_width
+    pub fn to_json(&self, source: &str, file_name: &str, tab_width: usize) -> String {
+        let spans: Vec<String> = self
+            .labels
+            .iter()
+            .map(|label| {
+                let (line, column) = line_col(source, label.span.start);
+                let line_start = source[..label.span.start.min(source.len())].rfind('\n').map_or(0, |i| i + 1);
+                let before = source.get(line_start..label.span.start).unwrap_or("");
+                let display_column = expand_tabs(before, tab_width).chars().count() + 1;
+                format!(
+                    r#"{{"start":{},"end":{},"line":{line},"column":{column},"display_column":{display_column},"label":{},"primary":{}}}"#,
+                    label.span.start,
+                    label.span.end,
+                    json_string(&label.message),
+                    label.primary
+                )
+            })
+            .collect();
+        let notes: Vec<String> = self
+            .notes
+            .iter()
+            .map(|(severity, note)| format!(r#"{{"severity":"{}","message":{}}}"#, severity.name(), json_string(note)))
+            .collect();
+        let suggestions: Vec<String> = self
+            .suggestions
+            .iter()
+            .map(|suggestion| {
+                format!(
+                    r#"{{"start":{},"end":{},"replacement":{},"message":{}}}"#,
+                    suggestion.span.start,
+                    suggestion.span.end,
+                    json_string(&suggestion.replacement),
+                    json_string(&suggestion.message)
+                )
+            })
+            .collect();
+        format!(
+            r#"{{"code":{},"message":{},"severity":"{}","file":{},"spans":[{}],"notes":[{}],"suggestions":[{}]}}"#,
+            self.code.map_or(String::from("null"), json_string),
+            json_string(&self.message),
+            self.severity.name(),
+            json_string(file_name),
+            spans.join(","),
+            notes.join(","),
+            suggestions.join(",")
+        )
+    }
+
+    /// emit formats the diagnostic in the requested format, always ending with a new line
+    pub fn emit(&self, source: &str, file_name: &str, format: ErrorFormat, color: bool, tab_width: usize) -> String {
+        match format {
+            ErrorFormat::Human => self.render(source, file_name, color, tab_width),
+            ErrorFormat::Json => format!("{}\n", self.to_json(source, file_name, tab_width)),
+        }
+    }
+}
+
+/// line_col converts a byte offset into a 1-based line and character column
+pub fn line_col(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, before[line_start..].chars().count() + 1)
+}
+
+/// use_color returns true if diagnostics written to stdout should be colored
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::DEFAULT_TAB_WIDTH;
+
+    #[test]
+    fn test_render_diagnostic() {
+        let source = "let x = 5;\nlet y = x @ 2;\n";
+        let diagnostic = Diagnostic::error("illegal character '@'")
+            .with_label(21..22, "not a valid token")
+            .with_secondary_label(4..5, "x is defined here")
+            .with_help("remove the character");
+
+        let expected = r#"error: illegal character '@'
+ --> main.nipl:2:11
+  |
+1 | let x = 5;
+  |     - x is defined here
+2 | let y = x @ 2;
+  |           ^ not a valid token
+  = help: remove the character
+"#;
+        assert_eq!(diagnostic.render(source, "main.nipl", false, DEFAULT_TAB_WIDTH), expected);
+
+        let colored = diagnostic.render(source, "main.nipl", true, DEFAULT_TAB_WIDTH);
+        assert!(colored.starts_with("\x1b[1;31merror\x1b[0m"));
+
+        // carets line up under tab-indented code
+        let tabbed = Diagnostic::error("illegal character '@'").with_label(6..7, "here");
+        assert_eq!(tabbed.render("x\n\t\ty @\n", "main.nipl", false, DEFAULT_TAB_WIDTH), "error: illegal character '@'\n --> main.nipl:2:5\n  |\n2 |         y @\n  |           ^ here\n");
+
+        // with wider tab stops the line and carets move out together
+        assert_eq!(tabbed.render("x\n\t\ty @\n", "main.nipl", false, 8), "error: illegal character '@'\n --> main.nipl:2:5\n  |\n2 |                 y @\n  |                   ^ here\n");
+        assert!(tabbed.to_json("x\n\t\ty @\n", "main.nipl", 8).contains(r#""column":5,"display_column":19,"#));
+
+        let bare = Diagnostic::warning("nothing to point at").render(source, "main.nipl", false, DEFAULT_TAB_WIDTH);
+        assert_eq!(bare, "warning: nothing to point at\n");
+    }
+
+    #[test]
+    fn test_diagnostic_to_json() {
+        let source = "let x = \"a\" @;";
+        let diagnostic = Diagnostic::error("illegal character '@'")
+            .with_code("E0001")
+            .with_label(12..13, "not a \"valid\" token")
+            .with_note("tokens are ASCII");
+
+        assert_eq!(
+            diagnostic.to_json(source, "main.nipl", DEFAULT_TAB_WIDTH),
+            r#"{"code":"E0001","message":"illegal character '@'","severity":"error","file":"main.nipl","spans":[{"start":12,"end":13,"line":1,"column":13,"display_column":13,"label":"not a \"valid\" token","primary":true}],"notes":[{"severity":"note","message":"tokens are ASCII"}],"suggestions":[]}"#
+        );
+        assert!(diagnostic.render(source, "main.nipl", false, DEFAULT_TAB_WIDTH).starts_with("error[E0001]: illegal"));
+        assert_eq!("json".parse::<ErrorFormat>(), Ok(ErrorFormat::Json));
+    }
+
+    #[test]
+    fn test_diagnostic_suggestions() {
+        let source = "let x = 1;\nif (x = 5) { x }\n";
+        let diagnostic = Diagnostic::warning("assignment used as a condition")
+            .with_label(17..18, "this assigns to x")
+            .with_suggestion(17..18, "==", "compare with");
+
+        assert_eq!(diagnostic.suggestions[0].apply(source), "let x = 1;\nif (x == 5) { x }\n");
+        assert!(diagnostic
+            .render(source, "main.nipl", false, DEFAULT_TAB_WIDTH)
+            .ends_with("  = help: compare with: `if (x == 5) { x }`\n"));
+        assert!(diagnostic
+            .to_json(source, "main.nipl", DEFAULT_TAB_WIDTH)
+            .ends_with(r#""suggestions":[{"start":17,"end":18,"replacement":"==","message":"compare with"}]}"#));
+    }
+}
@@ -0,0 +1,74 @@
+use std::io::{self, BufReader, Bytes, Read};
+
+// Utf8Chars decodes the characters of a reader one at a time, so the input never has to be held in
+// memory as a whole. Invalid UTF-8 is replaced with U+FFFD like String::from_utf8_lossy does
+pub struct Utf8Chars<R: Read> {
+    bytes: Bytes<BufReader<R>>,
+    pending: Option<u8>, // a byte read while checking a sequence that belongs to the next char
+}
+
+impl<R: Read> Utf8Chars<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            bytes: BufReader::new(reader).bytes(),
+            pending: None,
+        }
+    }
+
+    /// next_byte returns the byte put aside by the last char, if any, before reading a new one
+    fn next_byte(&mut self) -> Option<io::Result<u8>> {
+        match self.pending.take() {
+            Some(b) => Some(Ok(b)),
+            None => self.bytes.next(),
+        }
+    }
+}
+
+impl<R: Read> Iterator for Utf8Chars<R> {
+    type Item = io::Result<char>;
+
+    /// next returns the next decoded char, or the error that stopped the reader
+    fn next(&mut self) -> Option<io::Result<char>> {
+        let first = match self.next_byte()? {
+            Ok(b) => b,
+            Err(err) => return Some(Err(err)),
+        };
+        // the first byte tells how long the sequence is
+        let len = match first {
+            0x00..=0x7F => return Some(Ok(first as char)),
+            0xC0..=0xDF => 2,
+            0xE0..=0xEF => 3,
+            0xF0..=0xF7 => 4,
+            _ => return Some(Ok(char::REPLACEMENT_CHARACTER)),
+        };
+
+        let mut buf = [first, 0, 0, 0];
+        for b in buf.iter_mut().take(len).skip(1) {
+            match self.next_byte() {
+                Some(Ok(next)) if next & 0xC0 == 0x80 => *b = next,
+                // a sequence cut short by another char or the end of the input
+                Some(Ok(next)) => {
+                    self.pending = Some(next);
+                    return Some(Ok(char::REPLACEMENT_CHARACTER));
+                }
+                None => return Some(Ok(char::REPLACEMENT_CHARACTER)),
+                Some(Err(err)) => return Some(Err(err)),
+            }
+        }
+        // overlong encodings and surrogates have the right shape but are still invalid
+        let ch = std::str::from_utf8(&buf[..len]).ok().and_then(|s| s.chars().next());
+        Some(Ok(ch.unwrap_or(char::REPLACEMENT_CHARACTER)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_utf8_chars() {
+        let bytes: &[u8] = b"a\xC3\xA9\xF0\x9F\x98\x80\xFFb\xE5\x8Fc\xC0\xAF\xE2";
+        let chars: String = Utf8Chars::new(bytes).map(Result::unwrap).collect();
+        assert_eq!(chars, "aé😀\u{FFFD}b\u{FFFD}c\u{FFFD}\u{FFFD}");
+    }
+}
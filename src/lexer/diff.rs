@@ -0,0 +1,186 @@
+use std::borrow::Cow;
+
+use crate::lexer::lexer::Lexer;
+use crate::token::token::{StringPart, Token, TokenType};
+
+// TokenChange represents a single difference between two token streams
+#[derive(Debug, PartialEq)]
+pub enum TokenChange<'a> {
+    Removed(usize, Token<'a>), // index of the token in the old stream
+    Added(usize, Token<'a>),   // index of the token in the new stream
+}
+
+impl std::fmt::Display for TokenChange<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TokenChange::Removed(_, tok) => write!(f, "- {}:{} {:?}", tok.line, tok.column, tok.kind),
+            TokenChange::Added(_, tok) => write!(f, "+ {}:{} {:?}", tok.line, tok.column, tok.kind),
+        }
+    }
+}
+
+/// tokenize lexes the whole input and returns its tokens, leaving out the trailing EOF. The tokens
+/// borrow from the input when it is a &str
+pub fn tokenize<'a, T: Into<Cow<'a, str>>>(input: T) -> Vec<Token<'a>> {
+    Lexer::new(input).take_while(|tok| tok.kind != TokenType::EOF).collect()
+}
+
+/// diff_tokens compares two token streams by kind and returns the removals and additions
+/// needed to turn old into new. Whitespace never reaches the token stream so it is ignored
+pub fn diff_tokens<'a>(old: &[Token<'a>], new: &[Token<'a>]) -> Vec<TokenChange<'a>> {
+    // the unchanged start and end of the streams never need to go through the LCS
+    let prefix = old.iter().zip(new).take_while(|(a, b)| same_kind(a, b)).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| same_kind(a, b))
+        .count();
+    let (old_end, new_end) = (old.len() - suffix, new.len() - suffix);
+
+    let mut matches = Vec::new();
+    common_subsequence(&old[prefix..old_end], &new[prefix..new_end], (prefix, prefix), &mut matches);
+    // a sentinel past the end flushes the changes after the last match
+    matches.push((old_end, new_end));
+
+    // emit everything between two consecutive matches, removals first
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (prefix, prefix);
+    for (next_i, next_j) in matches {
+        changes.extend((i..next_i).map(|i| TokenChange::Removed(i, old[i].clone())));
+        changes.extend((j..next_j).map(|j| TokenChange::Added(j, new[j].clone())));
+        (i, j) = (next_i + 1, next_j + 1);
+    }
+    changes
+}
+
+/// same_kind returns true if two tokens are the same apart from where they are. Tokens nested in an
+/// interpolated string carry positions too, so those are compared by kind as well
+fn same_kind(a: &Token, b: &Token) -> bool {
+    match (&a.kind, &b.kind) {
+        (TokenType::InterpolatedString(a), TokenType::InterpolatedString(b)) => {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|parts| match parts {
+                    (StringPart::Text(a), StringPart::Text(b)) => a == b,
+                    (StringPart::Expr(a), StringPart::Expr(b)) => {
+                        a.len() == b.len() && a.iter().zip(b).all(|(a, b)| same_kind(a, b))
+                    }
+                    _ => false,
+                })
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// common_subsequence pushes the index pairs of a longest common subsequence of old and new, in order,
+/// using Hirschberg's divide and conquer so only two rows of the LCS table are held at a time.
+/// offset is the position of old[0] and new[0] in the full streams
+fn common_subsequence(old: &[Token], new: &[Token], offset: (usize, usize), matches: &mut Vec<(usize, usize)>) {
+    if old.is_empty() || new.is_empty() {
+        return;
+    }
+    if old.len() == 1 {
+        if let Some(j) = new.iter().position(|tok| same_kind(tok, &old[0])) {
+            matches.push((offset.0, offset.1 + j));
+        }
+        return;
+    }
+
+    // split old in half and find where new splits so both halves together keep the longest subsequence
+    let mid = old.len() / 2;
+    let forward = lcs_lengths(old[..mid].iter(), new.iter());
+    let backward = lcs_lengths(old[mid..].iter().rev(), new.iter().rev());
+    let split = (0..=new.len())
+        .max_by_key(|&j| (forward[j] + backward[new.len() - j], std::cmp::Reverse(j)))
+        .unwrap_or_default();
+
+    common_subsequence(&old[..mid], &new[..split], offset, matches);
+    common_subsequence(&old[mid..], &new[split..], (offset.0 + mid, offset.1 + split), matches);
+}
+
+/// lcs_lengths returns the last row of the LCS table of old and new: entry j holds the length of the
+/// longest common subsequence of old and the first j tokens of new
+fn lcs_lengths<'t, 'a: 't>(
+    old: impl Iterator<Item = &'t Token<'a>>,
+    new: impl Iterator<Item = &'t Token<'a>> + Clone,
+) -> Vec<usize> {
+    let mut row = vec![0_usize; new.clone().count() + 1];
+    for a in old {
+        // diagonal holds the previous row's entry for j - 1
+        let mut diagonal = 0;
+        for (j, b) in new.clone().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = match same_kind(a, b) {
+                true => diagonal + 1,
+                false => above.max(row[j]),
+            };
+            diagonal = above;
+        }
+    }
+    row
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_tokens() {
+        let old = tokenize("let x = 5;");
+        let same = tokenize("let   x=5;\n");
+        assert!(diff_tokens(&old, &same).is_empty());
+
+        let new = tokenize("let y = 5 + 1;");
+        let changes: Vec<String> = diff_tokens(&old, &new).iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            changes,
+            vec![
+                r#"- 1:5 Ident("x")"#,
+                r#"+ 1:5 Ident("y")"#,
+                "+ 1:11 Plus",
+                r#"+ 1:13 Int("1")"#,
+            ]
+        );
+
+        // applying the changes to old has to give new, checked on streams with repeats and shared ends
+        let old = tokenize("a b c a b b a ; x y z 1 2 3 ( ) fn let");
+        let new = tokenize("c b a b a c ; y z 1 2 x 3 ( ) fn let");
+        let changes = diff_tokens(&old, &new);
+        let mut patched: Vec<TokenType> = old.iter().map(|tok| tok.kind.clone()).collect();
+        for change in changes.iter().rev() {
+            if let TokenChange::Removed(i, _) = change {
+                patched.remove(*i);
+            }
+        }
+        for change in &changes {
+            if let TokenChange::Added(j, tok) = change {
+                patched.insert(*j, tok.kind.clone());
+            }
+        }
+        assert_eq!(patched, new.iter().map(|tok| tok.kind.clone()).collect::<Vec<_>>());
+        // and the diff is minimal, checked against the full quadratic LCS table
+        let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+        for i in (0..old.len()).rev() {
+            for j in (0..new.len()).rev() {
+                lcs[i][j] = match old[i].kind == new[j].kind {
+                    true => lcs[i + 1][j + 1] + 1,
+                    false => lcs[i + 1][j].max(lcs[i][j + 1]),
+                };
+            }
+        }
+        assert_eq!(changes.len(), old.len() + new.len() - 2 * lcs[0][0]);
+    }
+
+    #[test]
+    fn test_diff_interpolated_strings() {
+        // the tokens inside the string move along with it, which is not a change
+        let old = tokenize(r#"let s = "a${x + 1}";"#);
+        let moved = tokenize("let  s =\n  \"a${x+1}\";");
+        assert!(diff_tokens(&old, &moved).is_empty());
+
+        let new = tokenize(r#"let s = "a${x - 1}";"#);
+        let changes = diff_tokens(&old, &new);
+        assert_eq!(changes.len(), 2);
+        assert!(matches!(&changes[0], TokenChange::Removed(3, tok) if tok.literal == r#""a${x + 1}""#));
+    }
+}
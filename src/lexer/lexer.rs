@@ -1,26 +1,164 @@
-use crate::token::token::{Token, TokenType};
+use std::borrow::Cow;
+use std::io::{self, Read};
+use std::ops::Range;
+use crate::lexer::chars::Utf8Chars;
+use crate::token::token::{Location, StringPart, Token, TokenType, TokenWithTrivia, Trivia, TriviaKind};
 use crate::utils;
 
-// Lexer represents the lexer in tokenization
-pub struct Lexer {
-    pub input: Vec<char>,
-    pub position: usize, // current position in the input (points to current char)
-    pub read_position: usize, // current reading position in the input (after current char)
+// LOOKAHEAD is how many chars past the current one a streaming lexer keeps buffered, enough for the
+// longest peek, the '#'s of a raw string. The buffer is filled by bytes, up to 4 per char
+const LOOKAHEAD: usize = MAX_RAW_STRING_HASHES + 2;
+const LOOKAHEAD_BYTES: usize = LOOKAHEAD * 4;
+// MAX_RAW_STRING_HASHES is the most '#'s a raw string can be delimited with
+const MAX_RAW_STRING_HASHES: usize = 255;
+// COMPACT_AFTER is how many consumed bytes a streaming lexer lets pile up before dropping them
+const COMPACT_AFTER: usize = 4096;
+
+// OPERATORS maps every operator and delimiter, all ASCII, to its token kind. read_operator picks the
+// longest entry the input starts with, so a new multi-char operator only needs a line here
+const OPERATORS: &[(&str, TokenType<'static>)] = &[
+    ("=", TokenType::Assign),
+    ("==", TokenType::Eq),
+    ("!", TokenType::Bang),
+    ("!=", TokenType::NotEq),
+    ("<", TokenType::LT),
+    ("<=", TokenType::LTE),
+    ("<<", TokenType::ShiftLeft),
+    (">", TokenType::GT),
+    (">=", TokenType::GTE),
+    (">>", TokenType::ShiftRight),
+    ("&", TokenType::BitAnd),
+    ("&&", TokenType::And),
+    ("|", TokenType::BitOr),
+    ("||", TokenType::Or),
+    ("^", TokenType::BitXor),
+    ("~", TokenType::BitNot),
+    ("+", TokenType::Plus),
+    ("+=", TokenType::PlusAssign),
+    ("-", TokenType::Minus),
+    ("-=", TokenType::MinusAssign),
+    ("->", TokenType::Arrow),
+    ("/", TokenType::Slash),
+    ("/=", TokenType::SlashAssign),
+    ("*", TokenType::Asterisk),
+    ("*=", TokenType::AsteriskAssign),
+    ("**", TokenType::Power),
+    ("%", TokenType::Percent),
+    ("%=", TokenType::PercentAssign),
+    (".", TokenType::Dot),
+    ("..", TokenType::Range),
+    ("..=", TokenType::RangeInclusive),
+    (";", TokenType::Semicolon),
+    (":", TokenType::Colon),
+    (",", TokenType::Comma),
+    ("(", TokenType::LParen),
+    (")", TokenType::RParen),
+    ("{", TokenType::LBrace),
+    ("}", TokenType::RBrace),
+    ("[", TokenType::LBracket),
+    ("]", TokenType::RBracket),
+    ("?.", TokenType::SafeDot),
+    ("?[", TokenType::SafeLBracket),
+];
+
+// NoSource is the source of a lexer whose whole input is already in memory
+pub type NoSource = std::iter::Empty<io::Result<char>>;
+
+// CharSource is the source of a lexer created with from_chars
+pub type CharSource<I> = std::iter::Map<I, fn(char) -> io::Result<char>>;
+
+// Lexer represents the lexer in tokenization. It walks the input by byte index, so a lexer over a
+// borrowed &str hands out tokens whose literals are slices of it. S is where a streaming lexer pulls
+// its chars from, it is a type parameter so the lexer is Send whenever the source is and can borrow a reader
+pub struct Lexer<'a, S = NoSource> {
+    pub input: Cow<'a, str>,
+    pub position: usize, // current byte position in the input (points to current char)
+    pub read_position: usize, // current byte reading position in the input (after current char)
     pub current_char: Option<char>, // current char under examination
+    pub location: Location, // location of the current char
+    pub tab_width: usize, // columns between tab stops used for display columns
+    finished: bool, // set once the EOF token has been handed out by the iterator
+    source: Option<S>, // the chars not read into input yet when streaming
+    io_error: Option<io::Error>, // the error that ended a streamed input early
+}
+
+impl<'a> Lexer<'a> {
+    /// new creates a lexer over an input held in memory. Given a &str the tokens borrow their text from
+    /// it without copying, given a String they own copies of it
+    pub fn new<T: Into<Cow<'a, str>>>(input: T) -> Self {
+        Self::with_source(input.into(), None)
+    }
+
+    /// from_chars creates a lexer that pulls chars from the iterator as it needs them instead of
+    /// holding the whole input, keeping only the current token and a small lookahead in memory
+    pub fn from_chars<I: Iterator<Item = char>>(chars: I) -> Lexer<'static, CharSource<I>> {
+        Lexer::with_source(Cow::Owned(String::new()), Some(chars.map(Ok as fn(char) -> io::Result<char>)))
+    }
+
+    /// from_reader creates a lexer that streams UTF-8 text from the reader, see from_chars. Invalid UTF-8
+    /// is replaced with U+FFFD and an I/O error ends the input, it can be checked for with io_error
+    pub fn from_reader<R: Read>(reader: R) -> Lexer<'static, Utf8Chars<R>> {
+        Lexer::with_source(Cow::Owned(String::new()), Some(Utf8Chars::new(reader)))
+    }
 }
 
-impl Lexer {
-    pub fn new(input: String) -> Self {
+impl<'a, S: Iterator<Item = io::Result<char>>> Lexer<'a, S> {
+    fn with_source(input: Cow<'a, str>, source: Option<S>) -> Self {
         let mut l = Self {
-            input: input.chars().collect(),
+            input,
             position: 0,
             read_position: 0,
             current_char: None,
+            location: Location::start(),
+            tab_width: utils::DEFAULT_TAB_WIDTH,
+            finished: false,
+            source,
+            io_error: None,
         };
         l.read_char(); // point to the first char to read
         l
     }
 
+    /// io_error returns the error that cut a streamed input short, if any
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.io_error.as_ref()
+    }
+
+    /// fill pulls chars from a streamed source until the lookahead after the current char is buffered
+    fn fill(&mut self) {
+        let Some(source) = self.source.as_mut() else { return };
+        while self.input.len() < self.read_position + LOOKAHEAD_BYTES {
+            match source.next() {
+                Some(Ok(c)) => self.input.to_mut().push(c),
+                Some(Err(err)) => {
+                    self.io_error = Some(err);
+                    self.source = None;
+                    return;
+                }
+                None => {
+                    self.source = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// compact drops the chars of a streamed input that earlier tokens have been read from. It must only
+    /// be called between tokens since the read functions slice the input from where their token starts
+    fn compact(&mut self) {
+        if self.source.is_none() || self.position < COMPACT_AFTER {
+            return;
+        }
+        self.input.to_mut().drain(..self.position);
+        self.read_position -= self.position;
+        self.position = 0;
+    }
+
+    /// set_tab_width sets the number of columns between tab stops used to compute display columns
+    pub fn set_tab_width(&mut self, tab_width: usize) {
+        self.tab_width = tab_width;
+    }
+
     /// eat_whitespace skips any whitespace characters in the input string
     pub fn eat_whitespace(&mut self) {
         // while the current char is whitespace, read it and go to the next char
@@ -36,53 +174,377 @@ impl Lexer {
         }
     }
 
+    /// eat_line_comment skips a // comment up to the end of the line and returns true if there was one
+    pub fn eat_line_comment(&mut self) -> bool {
+        if self.current_char != Some('/') || self.peek_char() != Some('/') {
+            return false;
+        }
+        // leave the new line for eat_whitespace
+        while self.current_char.is_some_and(|c| c != '\n') {
+            self.read_char();
+        }
+        true
+    }
+
+    /// eat_block_comment skips a /* */ comment, which may contain nested block comments, and returns true
+    /// if there was one. A comment that is still open at the end of the input is returned as an error token
+    pub fn eat_block_comment(&mut self) -> Result<bool, Token<'a>> {
+        if self.current_char != Some('/') || self.peek_char() != Some('*') {
+            return Ok(false);
+        }
+        let start_position = self.position;
+        let location = self.location;
+        let mut depth = 0;
+        while let Some(c) = self.current_char {
+            match (c, self.peek_char()) {
+                ('/', Some('*')) => {
+                    depth += 1;
+                    self.read_char();
+                }
+                ('*', Some('/')) => {
+                    depth -= 1;
+                    self.read_char();
+                }
+                _ => {}
+            }
+            self.read_char();
+            if depth == 0 {
+                return Ok(true);
+            }
+        }
+        let literal = self.slice(start_position..self.input.len());
+        let mut tok = Token::new(TokenType::UnterminatedComment(depth), literal);
+        tok.set_location(location);
+        tok.set_end_offset(self.location.offset);
+        Err(tok)
+    }
+
     /// peek_char returns the next character in the token but DOES NOT advance the read or current position
     pub fn peek_char(&self) -> Option<char> {
         // if there is nothing more to read, return None else return the current character
-        match self.read_position < self.input.len() {
-            true => Some(self.input[self.read_position]),
-            false => None,
+        self.input.get(self.read_position..)?.chars().next()
+    }
+
+    /// peek_nth_char returns the character n places after the next one without advancing, peek_nth_char(0) is peek_char()
+    pub fn peek_nth_char(&self, n: usize) -> Option<char> {
+        self.input.get(self.read_position..)?.chars().nth(n)
+    }
+
+    /// slice returns the input in the byte range, borrowed from the input if the lexer borrows it
+    fn slice(&self, range: Range<usize>) -> Cow<'a, str> {
+        match &self.input {
+            Cow::Borrowed(input) => Cow::Borrowed(&input[range]),
+            Cow::Owned(input) => Cow::Owned(input[range].to_string()),
         }
     }
 
     /// read_char reads the next character in the token and advances the read position
     pub fn read_char(&mut self) {
+        self.fill();
+        // once the end of the input has been reached there is nothing left to advance over, which
+        // keeps position within the input so slicing up to it never goes out of bounds
+        if self.read_position > self.input.len() {
+            return;
+        }
+        // move the location past the character being left behind
+        if let Some(c) = self.current_char {
+            self.location.offset += c.len_utf8();
+            match c {
+                '\n' => {
+                    self.location.line += 1;
+                    self.location.column = 1;
+                    self.location.display_column = 1;
+                }
+                '\t' => {
+                    self.location.column += 1;
+                    self.location.display_column = utils::next_tab_stop(self.location.display_column, self.tab_width);
+                }
+                _ => {
+                    self.location.column += 1;
+                    self.location.display_column += 1;
+                }
+            }
+        }
         // get the next character if it exists
         self.current_char = self.peek_char();
-        // advance the position and read position
+        // advance the position and read position past the new current char
         self.position = self.read_position;
-        self.read_position += 1;
+        self.read_position += self.current_char.map_or(1, char::len_utf8);
     }
 
-    /// read_identifier keeps reading a word until there is no longer a letter
-    pub fn read_identifier(&mut self) -> String {
+    /// read_identifier keeps reading a word until there is no longer a letter, digit or underscore
+    pub fn read_identifier(&mut self) -> Cow<'a, str> {
         let current_position = self.position;
-        // while there is a letter to read, read it and move the read position
-        while utils::is_letter_or_underscore(self.current_char.clone().unwrap()) {
+        // while there is an identifier character to read, read it and move the read position
+        while self.current_char.is_some_and(utils::is_identifier_char) {
             self.read_char();
         }
-        self.input[current_position..self.position]
-            .to_vec()
-            .iter()
-            .collect::<String>()
+        self.slice(current_position..self.position)
+    }
+
+    /// read_digit keeps reading a word until there is no longer a digit. Underscores between digits,
+    /// as in 1_000_000, are read as part of the word
+    pub fn read_digit(&mut self) -> Cow<'a, str> {
+        self.read_digits_in_radix(10)
     }
 
-    /// read_digit keeps reading a word until there is no longer a digit
-    pub fn read_digit(&mut self) -> String {
+    /// read_digits_in_radix keeps reading digits of the given radix, allowing underscores between them
+    fn read_digits_in_radix(&mut self, radix: u32) -> Cow<'a, str> {
         let current_position = self.position;
-        // while there is a digit to read, read it and move the read position
-        while utils::is_digit(self.current_char.clone().unwrap()) {
+        // while there is a digit, or an underscore followed by one, read it and move the read position
+        while let Some(c) = self.current_char {
+            let is_separator = c == '_' && self.peek_char().is_some_and(|next| next.is_digit(radix));
+            if !c.is_digit(radix) && !is_separator {
+                break;
+            }
             self.read_char();
         }
-        self.input[current_position..self.position]
-            .to_vec()
-            .iter()
-            .collect::<String>()
+        self.slice(current_position..self.position)
+    }
+
+    /// read_radix_integer reads a 0x, 0o or 0b prefixed integer. The token kind holds the value in decimal
+    /// while the literal keeps it as written. It returns None, reading nothing, if no such literal starts here
+    pub fn read_radix_integer(&mut self) -> Option<Token<'a>> {
+        let radix = match (self.current_char, self.peek_char()) {
+            (Some('0'), Some('x' | 'X')) => 16,
+            (Some('0'), Some('o' | 'O')) => 8,
+            (Some('0'), Some('b' | 'B')) => 2,
+            _ => return None,
+        };
+        // separators may come right after the prefix, but a prefix without any digits is just a zero
+        // followed by an identifier
+        let mut n = 1;
+        while self.peek_nth_char(n) == Some('_') {
+            n += 1;
+        }
+        if !self.peek_nth_char(n).is_some_and(|c| c.is_digit(radix) || utils::is_digit(c)) {
+            return None;
+        }
+
+        let current_position = self.position;
+        self.read_char(); // the '0'
+        self.read_char(); // the radix letter
+        while self.current_char == Some('_') {
+            self.read_char();
+        }
+        let digits = self.read_digits_in_radix(radix).replace('_', "");
+
+        // a decimal digit the radix doesn't allow, e.g. the 2 in 0b102, makes the whole literal invalid
+        if let Some(invalid) = self.current_char.filter(|&c| utils::is_digit(c)) {
+            while self.current_char.is_some_and(|c| utils::is_digit(c) || c == '_') {
+                self.read_char();
+            }
+            let literal = self.slice(current_position..self.position);
+            let message = format!("invalid digit '{invalid}' for a base {radix} literal");
+            return Some(Token::new(TokenType::Illegal(message), literal));
+        }
+
+        let literal = self.slice(current_position..self.position);
+        match u128::from_str_radix(&digits, radix) {
+            Ok(value) => Some(Token::new(TokenType::Int(Cow::Owned(value.to_string())), literal)),
+            // too large to normalize
+            Err(_) => Some(Token::new(TokenType::Illegal(format!("integer literal '{literal}' is too large")), literal)),
+        }
+    }
+
+    /// read_number reads an integer, or a float if the digits are followed by a fraction or an exponent
+    pub fn read_number(&mut self) -> Token<'a> {
+        if let Some(tok) = self.read_radix_integer() {
+            return tok;
+        }
+
+        let current_position = self.position;
+        let mut is_float = false;
+        self.read_digit();
+
+        // a fraction needs a digit after the dot so that `1.` and `1..2` leave the dot alone
+        if self.current_char == Some('.') && self.peek_char().is_some_and(utils::is_digit) {
+            is_float = true;
+            self.read_char();
+            self.read_digit();
+        }
+
+        // an exponent needs at least one digit, optionally after a sign, e.g. 1e9 or 2.5e-3
+        if matches!(self.current_char, Some('e' | 'E')) {
+            let sign = matches!(self.peek_char(), Some('+' | '-'));
+            let digit_offset = if sign { 1 } else { 0 };
+            if self.peek_nth_char(digit_offset).is_some_and(utils::is_digit) {
+                is_float = true;
+                self.read_char(); // the 'e'
+                if sign {
+                    self.read_char();
+                }
+                self.read_digit();
+            }
+        }
+
+        let literal = self.slice(current_position..self.position);
+        // the value only needs its own copy if there are separators to leave out
+        let value = match literal.contains('_') {
+            true => Cow::Owned(literal.replace('_', "")),
+            false => literal.clone(),
+        };
+        match is_float {
+            true => Token::new(TokenType::Float(value), literal),
+            false => Token::new(TokenType::Int(value), literal),
+        }
+    }
+
+    /// read_string reads a double quoted string literal starting at the opening quote and stops on the
+    /// closing quote. Escape sequences are decoded in the token kind while the literal keeps the raw text.
+    /// A string containing ${expr} becomes an InterpolatedString with the tokens of every expression
+    pub fn read_string(&mut self) -> Token<'a> {
+        let start_position = self.position;
+        let mut parts = Vec::new();
+        self.read_char(); // the opening quote
+        // text is sliced from the input since the last escape sequence or interpolation, so a string
+        // without escapes borrows its value. decoded holds the text before that once there is an escape
+        let mut text_start = self.position;
+        let mut decoded: Option<String> = None;
+        loop {
+            match self.current_char {
+                Some('\\') => {
+                    let value = decoded.get_or_insert_with(String::new);
+                    value.push_str(&self.input[text_start..self.position]);
+                    self.read_char();
+                    match self.current_char {
+                        Some('n') => value.push('\n'),
+                        Some('t') => value.push('\t'),
+                        Some('r') => value.push('\r'),
+                        Some('"') => value.push('"'),
+                        Some('\\') => value.push('\\'),
+                        Some('$') => value.push('$'),
+                        // keep unknown escape sequences as they were written
+                        Some(c) => {
+                            value.push('\\');
+                            value.push(c);
+                        }
+                        None => {}
+                    }
+                    self.read_char();
+                    text_start = self.position;
+                }
+                Some('$') if self.peek_char() == Some('{') => {
+                    let text = self.take_text(&mut decoded, text_start);
+                    if !text.is_empty() {
+                        parts.push(StringPart::Text(text));
+                    }
+                    parts.push(StringPart::Expr(self.read_interpolation()));
+                    text_start = self.position;
+                }
+                // the closing quote, or the input ended before it
+                Some('"') | None => break,
+                Some(_) => self.read_char(),
+            }
+        }
+
+        let value = self.take_text(&mut decoded, text_start);
+        let end_position = (self.position + 1).min(self.input.len());
+        let literal = self.slice(start_position..end_position);
+        match (self.current_char, parts.is_empty()) {
+            (None, _) => Token::new(TokenType::UnterminatedString(value.into_owned()), literal),
+            (Some(_), true) => Token::new(TokenType::String(value), literal),
+            (Some(_), false) => {
+                if !value.is_empty() {
+                    parts.push(StringPart::Text(value));
+                }
+                Token::new(TokenType::InterpolatedString(parts), literal)
+            }
+        }
+    }
+
+    /// take_text returns the text of a string from text_start up to the current char, after the text
+    /// decoded before it if there is any
+    fn take_text(&self, decoded: &mut Option<String>, text_start: usize) -> Cow<'a, str> {
+        let text = self.slice(text_start.min(self.position)..self.position);
+        match decoded.take() {
+            Some(mut value) => {
+                value.push_str(&text);
+                Cow::Owned(value)
+            }
+            None => text,
+        }
+    }
+
+    /// read_interpolation reads the tokens of a ${expr} in a string, starting at the '$' and stopping
+    /// on the char after the closing brace. Braces inside the expression have to be balanced
+    fn read_interpolation(&mut self) -> Vec<Token<'a>> {
+        self.read_char(); // the '$'
+        self.read_char(); // the '{'
+        let mut tokens = Vec::new();
+        let mut depth = 0;
+        loop {
+            let tok = self.lex_token();
+            match tok.kind {
+                TokenType::RBrace if depth == 0 => break,
+                TokenType::LBrace => depth += 1,
+                TokenType::RBrace => depth -= 1,
+                // the input ended inside the expression, read_string reports the string as unterminated
+                TokenType::EOF | TokenType::UnterminatedComment(_) => break,
+                _ => {}
+            }
+            tokens.push(tok);
+        }
+        tokens
+    }
+
+    /// raw_string_hashes returns the number of '#' between the 'r' and the opening quote if the current
+    /// char starts a raw string like r"\d+" or r#"say "hi""#, and None otherwise
+    fn raw_string_hashes(&self) -> Option<usize> {
+        if self.current_char != Some('r') {
+            return None;
+        }
+        let hashes = (0..MAX_RAW_STRING_HASHES).take_while(|&n| self.peek_nth_char(n) == Some('#')).count();
+        (self.peek_nth_char(hashes) == Some('"')).then_some(hashes)
+    }
+
+    /// read_raw_string reads a raw string literal starting at the 'r' and stops on the last char of its
+    /// closing delimiter. Nothing is escaped, the string ends at the first quote followed by as many '#'
+    /// as it was opened with
+    pub fn read_raw_string(&mut self) -> Token<'a> {
+        let start_position = self.position;
+        let hashes = self.raw_string_hashes().unwrap_or_default();
+        for _ in 0..hashes + 2 {
+            self.read_char(); // the 'r', the '#'s and the opening quote
+        }
+
+        let value_position = self.position;
+        loop {
+            match self.current_char {
+                Some('"') if (0..hashes).all(|n| self.peek_nth_char(n) == Some('#')) => break,
+                Some(_) => self.read_char(),
+                // the input ended before the closing delimiter
+                None => break,
+            }
+        }
+        let value = self.slice(value_position..self.position);
+        if self.current_char.is_some() {
+            for _ in 0..hashes {
+                self.read_char();
+            }
+        }
+
+        let end_position = (self.position + 1).min(self.input.len());
+        let literal = self.slice(start_position..end_position);
+        match self.current_char {
+            Some(_) => Token::new(TokenType::String(value), literal),
+            None => Token::new(TokenType::UnterminatedString(value.into_owned()), literal),
+        }
+    }
+
+    /// read_illegal keeps reading until it reaches whitespace or a character that starts a token
+    pub fn read_illegal(&mut self) -> Cow<'a, str> {
+        let current_position = self.position;
+        self.read_char();
+        while self.current_char.is_some_and(|c| !self.is_token_start(c)) {
+            self.read_char();
+        }
+        self.slice(current_position..self.position)
     }
 
     /// lookup_identifier looks up an the identifier in the list of keywords
-    pub fn lookup_identifier(&self, s: &str) -> TokenType {
-        match s {
+    pub fn lookup_identifier(&self, s: Cow<'a, str>) -> TokenType<'a> {
+        match s.as_ref() {
             "let" => TokenType::Let,
             "fn" => TokenType::Function,
             "if" => TokenType::If,
@@ -90,62 +552,136 @@ impl Lexer {
             "return" => TokenType::Return,
             "true" => TokenType::True,
             "false" => TokenType::False,
-            _ => TokenType::Ident(s.to_string())
+            "in" => TokenType::In,
+            "while" => TokenType::While,
+            "for" => TokenType::For,
+            "break" => TokenType::Break,
+            "continue" => TokenType::Continue,
+            "null" => TokenType::Null,
+            "const" => TokenType::Const,
+            _ => TokenType::Ident(s)
         }
     }
 
-    /// check_two_char_token_eq handles scenarios where the token is potentially a comparison token, e.g. == and !=
-    fn check_two_char_token_eq(&mut self, single_kind: TokenType, double_kind: TokenType) -> Token {
-        if self.peek_char().unwrap_or(0_u8 as char) == '=' {
-            let curr_char = self.current_char.expect("next char should be '='");
-            self.read_char(); // set current character to the next character after reading it
-            return Token::new(double_kind, format!("{}{}", curr_char, self.current_char.expect("next char should be '='")))
+    /// match_operator returns the longest entry of OPERATORS that the input starts with at the current char
+    fn match_operator(&self) -> Option<&'static (&'static str, TokenType<'static>)> {
+        let rest = self.input.get(self.position..).unwrap_or_default();
+        OPERATORS
+            .iter()
+            .filter(|(op, _)| rest.starts_with(op))
+            .max_by_key(|(op, _)| op.len())
+    }
+
+    /// is_token_start returns true if ch, the current char, is whitespace or starts a valid token. A char
+    /// that only begins a longer operator, such as the '?' of '?.', has to be followed by the rest of it
+    fn is_token_start(&self, ch: char) -> bool {
+        ch.is_whitespace()
+            || utils::is_letter_or_underscore(ch)
+            || utils::is_digit(ch)
+            || ch == '"'
+            || self.match_operator().is_some()
+    }
+
+    /// read_operator reads the longest operator in OPERATORS that the input starts with, leaving the
+    /// current char on its last char. It returns None if no operator starts at the current char
+    fn read_operator(&mut self) -> Option<Token<'a>> {
+        let (op, kind) = self.match_operator()?;
+        for _ in 1..op.len() {
+            self.read_char();
         }
-        return Token::new(single_kind, self.current_char.expect("next char should be '='"));
+        Some(Token::new(kind.clone(), *op))
     }
 
     /// next_token returns the next token in the sequence
-    pub fn next_token(&mut self) -> Token {
-        // eat any whitespaces before processing the next character
-        self.eat_whitespace();
+    pub fn next_token(&mut self) -> Token<'a> {
+        self.compact();
+        self.lex_token()
+    }
 
+    /// next_token_with_trivia returns the next token along with the whitespace and comments before it, so
+    /// the input can be rebuilt exactly from the trivia texts and token literals. The EOF token's literal
+    /// is empty here rather than "\0", since it stands for no text in the input
+    pub fn next_token_with_trivia(&mut self) -> TokenWithTrivia<'a> {
+        self.compact();
+        let mut leading_trivia = Vec::new();
+        let mut token = self.lex_token_keeping(Some(&mut leading_trivia));
+        if token.kind == TokenType::EOF {
+            token.set_literal_str("");
+        }
+        TokenWithTrivia::new(leading_trivia, token)
+    }
+
+    /// lex_token skips whitespace and comments and reads the token after them
+    fn lex_token(&mut self) -> Token<'a> {
+        self.lex_token_keeping(None)
+    }
+
+    /// lex_token_keeping reads the next token like lex_token, adding the trivia it skips to trivia if given
+    fn lex_token_keeping(&mut self, mut trivia: Option<&mut Vec<Trivia<'a>>>) -> Token<'a> {
+        // eat any whitespaces and comments before processing the next character
+        loop {
+            let (start_position, location) = (self.position, self.location);
+            self.eat_whitespace();
+            let kind = if self.position > start_position {
+                TriviaKind::Whitespace
+            } else if self.eat_line_comment() {
+                TriviaKind::LineComment
+            } else {
+                match self.eat_block_comment() {
+                    Ok(true) => TriviaKind::BlockComment,
+                    Ok(false) => break,
+                    // an unterminated comment has swallowed the rest of the input
+                    Err(tok) => return tok,
+                }
+            };
+            if let Some(trivia) = trivia.as_deref_mut() {
+                trivia.push(Trivia::new(kind, self.slice(start_position..self.position), location));
+            }
+        }
+
+        // remember where the token starts before reading it, it ends where the next char starts
+        let location = self.location;
+        let mut tok = self.read_token();
+        tok.set_location(location);
+        tok.set_end_offset(self.location.offset);
+        tok
+    }
+
+    /// read_token reads the token that starts at the current character
+    fn read_token(&mut self) -> Token<'a> {
         // if the current_char is None, return a token with the byte 0
         let ch = match self.current_char {
             Some(ch) => ch,
-            None => return Token::new(TokenType::EOF, 0_u8 as char),
+            None => return Token::new(TokenType::EOF, "\0"),
         };
 
+        if let Some(tok) = self.read_operator() {
+            // advance past the last char of the operator
+            self.read_char();
+            return tok;
+        }
+
         let tok = match ch {
-            '=' => self.check_two_char_token_eq(TokenType::Assign, TokenType::Eq),
-            '!' => self.check_two_char_token_eq(TokenType::Bang, TokenType::NotEq),
-            '<' => self.check_two_char_token_eq(TokenType::LT, TokenType::LTE),
-            '>' => self.check_two_char_token_eq(TokenType::GT, TokenType::GTE),
-            '+' => Token::new(TokenType::Plus, ch),
-            '-' => Token::new(TokenType::Minus, ch),
-            '/' => Token::new(TokenType::Slash, ch),
-            '*' => Token::new(TokenType::Asterisk, ch),
-            ';' => Token::new(TokenType::Semicolon, ch),
-            '(' => Token::new(TokenType::LParen, ch),
-            ')' => Token::new(TokenType::RParen, ch),
-            ',' => Token::new(TokenType::Comma, ch),
-            '{' => Token::new(TokenType::LBrace, ch),
-            '}' => Token::new(TokenType::RBrace, ch),
+            '"' => self.read_string(),
+            'r' if self.raw_string_hashes().is_some() => self.read_raw_string(),
             _ => {
-                // create a default illegal token
-                let mut tok = Token::new(TokenType::Illegal, ch);
                 // if the current char is a letter, read the whole word as an identifier
                 if utils::is_letter_or_underscore(ch) {
-                    // set the word as the literal
-                    tok.set_literal_str(self.read_identifier());
+                    let literal = self.read_identifier();
                     // check if it is a keyword and set appropriately
-                    tok.set_kind(self.lookup_identifier(tok.literal.as_str()));
-                } else if utils::is_digit(ch) {
-                    // set the digit as the literal
-                    tok.set_literal_str(self.read_digit());
-                    // set the type to be an integer
-                    tok.set_kind(TokenType::Int(tok.literal.clone()))
+                    return Token::new(self.lookup_identifier(literal.clone()), literal);
+                }
+                // read the whole number, either an integer or a float
+                if utils::is_digit(ch) {
+                    return self.read_number();
                 }
-                return tok;
+                // gather the whole run of illegal characters into one token so lexing carries on after it
+                let literal = self.read_illegal();
+                let message = match literal.chars().count() {
+                    1 => format!("illegal character '{literal}'"),
+                    _ => format!("illegal characters '{literal}'"),
+                };
+                return Token::new(TokenType::Illegal(message), literal);
             }
         };
 
@@ -156,9 +692,24 @@ impl Lexer {
     }
 }
 
+impl<'a, S: Iterator<Item = io::Result<char>>> Iterator for Lexer<'a, S> {
+    type Item = Token<'a>;
+
+    /// next returns the tokens in order, ending with a single EOF token
+    fn next(&mut self) -> Option<Token<'a>> {
+        if self.finished {
+            return None;
+        }
+        let tok = self.next_token();
+        self.finished = tok.kind == TokenType::EOF;
+        Some(tok)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::borrow::Cow;
 
     #[test]
     fn test_next_token() {
@@ -171,7 +722,7 @@ mod tests {
         };
 
         let result = add(five, ten);
-        !-/*5;
+        !-/ *5;
         5 < 10 > 5;
 
         if (5 < 10) {
@@ -184,63 +735,75 @@ mod tests {
         10 != 9;
         10 <= 11;
         10 >= 9;
+        k in h;
+        10 % 3;
+        a && b || c;
+        ~a & b | c ^ 1 << 2 >> 3;
+        x += 1; x -= 2; x *= 3; x /= 4; x %= 5;
+        [1, 2];
+        2 ** 3 * * 4;
+        {"a": 1}
+        let café = x1 + 变量 + αβγ_2 + नमस्ते;
+        0..10; 1..=n; a.b; arr.len(); 1.5.x;
+        fn(x) -> x * 2; a->b; a- >b;
+        while for break continue null const constant
         "#,
         );
 
         let test_cases = vec![
             TokenType::Let,
-            TokenType::Ident(String::from("_five")),
+            TokenType::Ident(Cow::from("_five")),
             TokenType::Assign,
-            TokenType::Int(String::from("5")),
+            TokenType::Int(Cow::from("5")),
             TokenType::Semicolon,
             TokenType::Let,
-            TokenType::Ident(String::from("ten")),
+            TokenType::Ident(Cow::from("ten")),
             TokenType::Assign,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::Semicolon,
             TokenType::Let,
-            TokenType::Ident(String::from("add")),
+            TokenType::Ident(Cow::from("add")),
             TokenType::Assign,
             TokenType::Function,
             TokenType::LParen,
-            TokenType::Ident(String::from("x")),
+            TokenType::Ident(Cow::from("x")),
             TokenType::Comma,
-            TokenType::Ident(String::from("y")),
+            TokenType::Ident(Cow::from("y")),
             TokenType::RParen,
             TokenType::LBrace,
-            TokenType::Ident(String::from("x")),
+            TokenType::Ident(Cow::from("x")),
             TokenType::Plus,
-            TokenType::Ident(String::from("y")),
+            TokenType::Ident(Cow::from("y")),
             TokenType::Semicolon,
             TokenType::RBrace,
             TokenType::Semicolon,
             TokenType::Let,
-            TokenType::Ident(String::from("result")),
+            TokenType::Ident(Cow::from("result")),
             TokenType::Assign,
-            TokenType::Ident(String::from("add")),
+            TokenType::Ident(Cow::from("add")),
             TokenType::LParen,
-            TokenType::Ident(String::from("five")),
+            TokenType::Ident(Cow::from("five")),
             TokenType::Comma,
-            TokenType::Ident(String::from("ten")),
+            TokenType::Ident(Cow::from("ten")),
             TokenType::RParen,
             TokenType::Semicolon,
             TokenType::Bang,
             TokenType::Minus,
             TokenType::Slash,
             TokenType::Asterisk,
-            TokenType::Int(String::from("5")),
+            TokenType::Int(Cow::from("5")),
             TokenType::Semicolon,
-            TokenType::Int(String::from("5")),
+            TokenType::Int(Cow::from("5")),
             TokenType::LT,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::GT,
-            TokenType::Int(String::from("5")),
+            TokenType::Int(Cow::from("5")),
             TokenType::Semicolon,
             TokenType::If,
             TokenType::LParen,
-            TokenType::Int(String::from("5")),
+            TokenType::Int(Cow::from("5")),
             TokenType::LT,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::RParen,
             TokenType::LBrace,
             TokenType::Return,
@@ -253,22 +816,439 @@ mod tests {
             TokenType::False,
             TokenType::Semicolon,
             TokenType::RBrace,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::Eq,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::Semicolon,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::NotEq,
-            TokenType::Int(String::from("9")),
+            TokenType::Int(Cow::from("9")),
             TokenType::Semicolon,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::LTE,
-            TokenType::Int(String::from("11")),
+            TokenType::Int(Cow::from("11")),
             TokenType::Semicolon,
-            TokenType::Int(String::from("10")),
+            TokenType::Int(Cow::from("10")),
             TokenType::GTE,
-            TokenType::Int(String::from("9")),
+            TokenType::Int(Cow::from("9")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("k")),
+            TokenType::In,
+            TokenType::Ident(Cow::from("h")),
+            TokenType::Semicolon,
+            TokenType::Int(Cow::from("10")),
+            TokenType::Percent,
+            TokenType::Int(Cow::from("3")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("a")),
+            TokenType::And,
+            TokenType::Ident(Cow::from("b")),
+            TokenType::Or,
+            TokenType::Ident(Cow::from("c")),
+            TokenType::Semicolon,
+            TokenType::BitNot,
+            TokenType::Ident(Cow::from("a")),
+            TokenType::BitAnd,
+            TokenType::Ident(Cow::from("b")),
+            TokenType::BitOr,
+            TokenType::Ident(Cow::from("c")),
+            TokenType::BitXor,
+            TokenType::Int(Cow::from("1")),
+            TokenType::ShiftLeft,
+            TokenType::Int(Cow::from("2")),
+            TokenType::ShiftRight,
+            TokenType::Int(Cow::from("3")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::PlusAssign,
+            TokenType::Int(Cow::from("1")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::MinusAssign,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::AsteriskAssign,
+            TokenType::Int(Cow::from("3")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::SlashAssign,
+            TokenType::Int(Cow::from("4")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::PercentAssign,
+            TokenType::Int(Cow::from("5")),
+            TokenType::Semicolon,
+            TokenType::LBracket,
+            TokenType::Int(Cow::from("1")),
+            TokenType::Comma,
+            TokenType::Int(Cow::from("2")),
+            TokenType::RBracket,
+            TokenType::Semicolon,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Power,
+            TokenType::Int(Cow::from("3")),
+            TokenType::Asterisk,
+            TokenType::Asterisk,
+            TokenType::Int(Cow::from("4")),
+            TokenType::Semicolon,
+            TokenType::LBrace,
+            TokenType::String(Cow::from("a")),
+            TokenType::Colon,
+            TokenType::Int(Cow::from("1")),
+            TokenType::RBrace,
+            TokenType::Let,
+            TokenType::Ident(Cow::from("café")),
+            TokenType::Assign,
+            TokenType::Ident(Cow::from("x1")),
+            TokenType::Plus,
+            TokenType::Ident(Cow::from("变量")),
+            TokenType::Plus,
+            TokenType::Ident(Cow::from("αβγ_2")),
+            TokenType::Plus,
+            TokenType::Ident(Cow::from("नमस्ते")),
+            TokenType::Semicolon,
+            TokenType::Int(Cow::from("0")),
+            TokenType::Range,
+            TokenType::Int(Cow::from("10")),
+            TokenType::Semicolon,
+            TokenType::Int(Cow::from("1")),
+            TokenType::RangeInclusive,
+            TokenType::Ident(Cow::from("n")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("a")),
+            TokenType::Dot,
+            TokenType::Ident(Cow::from("b")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("arr")),
+            TokenType::Dot,
+            TokenType::Ident(Cow::from("len")),
+            TokenType::LParen,
+            TokenType::RParen,
+            TokenType::Semicolon,
+            TokenType::Float(Cow::from("1.5")),
+            TokenType::Dot,
+            TokenType::Ident(Cow::from("x")),
             TokenType::Semicolon,
+            TokenType::Function,
+            TokenType::LParen,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::RParen,
+            TokenType::Arrow,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::Asterisk,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("a")),
+            TokenType::Arrow,
+            TokenType::Ident(Cow::from("b")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("a")),
+            TokenType::Minus,
+            TokenType::GT,
+            TokenType::Ident(Cow::from("b")),
+            TokenType::Semicolon,
+            TokenType::While,
+            TokenType::For,
+            TokenType::Break,
+            TokenType::Continue,
+            TokenType::Null,
+            TokenType::Const,
+            TokenType::Ident(Cow::from("constant")),
+            TokenType::EOF,
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for test_case in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, test_case);
+        }
+    }
+
+    #[test]
+    fn test_identifier_chars() {
+        // a virama (Mn), a vowel sign (Mc) and a connector other than '_' (Pc) all continue an identifier
+        for name in ["x\u{94d}", "क\u{93e}", "a\u{203f}b", "e\u{301}\u{302}"] {
+            let mut l = Lexer::new(format!("{} ", name));
+            assert_eq!(l.next_token().kind, TokenType::Ident(Cow::from(name)));
+            assert_eq!(l.next_token().kind, TokenType::EOF);
+        }
+
+        // but a mark can't start one
+        let mut l = Lexer::new(String::from("\u{93e}x \u{301}"));
+        assert_eq!(l.next_token().kind, TokenType::Illegal(String::from("illegal character '\u{93e}'")));
+        assert_eq!(l.next_token().kind, TokenType::Ident(Cow::from("x")));
+        assert_eq!(l.next_token().kind, TokenType::Illegal(String::from("illegal character '\u{301}'")));
+    }
+
+    #[test]
+    fn test_safe_access_operators() {
+        let input = String::from("a?.b?.c arr?[0] x ?? y?.5");
+
+        let test_cases = vec![
+            (TokenType::Ident(Cow::from("a")), "a"),
+            (TokenType::SafeDot, "?."),
+            (TokenType::Ident(Cow::from("b")), "b"),
+            (TokenType::SafeDot, "?."),
+            (TokenType::Ident(Cow::from("c")), "c"),
+            (TokenType::Ident(Cow::from("arr")), "arr"),
+            (TokenType::SafeLBracket, "?["),
+            (TokenType::Int(Cow::from("0")), "0"),
+            (TokenType::RBracket, "]"),
+            (TokenType::Ident(Cow::from("x")), "x"),
+            // a '?' on its own is still not an operator, and a run of them is one illegal token
+            (TokenType::Illegal(String::from("illegal characters '??'")), "??"),
+            (TokenType::Ident(Cow::from("y")), "y"),
+            (TokenType::SafeDot, "?."),
+            (TokenType::Int(Cow::from("5")), "5"),
+            (TokenType::EOF, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, literal) in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, kind);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_number_literals() {
+        let input = String::from("5 3.14 0.5 1e9 2.5e-3 7E+2 1. 1..2 3e x 1.5e; 1_000_000 3.141_592 1e1_0 1__0;");
+
+        let test_cases = vec![
+            TokenType::Int(Cow::from("5")),
+            TokenType::Float(Cow::from("3.14")),
+            TokenType::Float(Cow::from("0.5")),
+            TokenType::Float(Cow::from("1e9")),
+            TokenType::Float(Cow::from("2.5e-3")),
+            TokenType::Float(Cow::from("7E+2")),
+            TokenType::Int(Cow::from("1")),
+            TokenType::Dot,
+            TokenType::Int(Cow::from("1")),
+            TokenType::Range,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Int(Cow::from("3")),
+            TokenType::Ident(Cow::from("e")),
+            TokenType::Ident(Cow::from("x")),
+            TokenType::Float(Cow::from("1.5")),
+            TokenType::Ident(Cow::from("e")),
+            TokenType::Semicolon,
+            TokenType::Int(Cow::from("1000000")),
+            TokenType::Float(Cow::from("3.141592")),
+            TokenType::Float(Cow::from("1e10")),
+            TokenType::Int(Cow::from("1")),
+            TokenType::Ident(Cow::from("__0")),
+            TokenType::Semicolon,
+            TokenType::EOF,
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for test_case in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, test_case);
+        }
+    }
+
+    #[test]
+    fn test_radix_integer_literals() {
+        let input = String::from("0xFF 0Xff 0o755 0b1010 0b102 0x 0o9 0xFF_FF 0b1_ 0x_1F 0o__7 0b_ 0b1_02 0x1_0000_0000_0000_0000_0000_0000_0000_0000;");
+
+        let test_cases = vec![
+            (TokenType::Int(Cow::from("255")), "0xFF"),
+            (TokenType::Int(Cow::from("255")), "0Xff"),
+            (TokenType::Int(Cow::from("493")), "0o755"),
+            (TokenType::Int(Cow::from("10")), "0b1010"),
+            (TokenType::Illegal(String::from("invalid digit '2' for a base 2 literal")), "0b102"),
+            (TokenType::Int(Cow::from("0")), "0"),
+            (TokenType::Ident(Cow::from("x")), "x"),
+            (TokenType::Illegal(String::from("invalid digit '9' for a base 8 literal")), "0o9"),
+            (TokenType::Int(Cow::from("65535")), "0xFF_FF"),
+            (TokenType::Int(Cow::from("1")), "0b1"),
+            (TokenType::Ident(Cow::from("_")), "_"),
+            (TokenType::Int(Cow::from("31")), "0x_1F"),
+            (TokenType::Int(Cow::from("7")), "0o__7"),
+            (TokenType::Int(Cow::from("0")), "0"),
+            (TokenType::Ident(Cow::from("b_")), "b_"),
+            (TokenType::Illegal(String::from("invalid digit '2' for a base 2 literal")), "0b1_02"),
+            (
+                TokenType::Illegal(String::from("integer literal '0x1_0000_0000_0000_0000_0000_0000_0000_0000' is too large")),
+                "0x1_0000_0000_0000_0000_0000_0000_0000_0000",
+            ),
+            (TokenType::Semicolon, ";"),
+            (TokenType::EOF, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, literal) in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, kind);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_token_positions() {
+        let input = String::from("let x = 5;\n  x == \"a\nb\" /* c\n */ café\n\n\tfoo");
+
+        let test_cases = vec![
+            (TokenType::Let, 1, 1),
+            (TokenType::Ident(Cow::from("x")), 1, 5),
+            (TokenType::Assign, 1, 7),
+            (TokenType::Int(Cow::from("5")), 1, 9),
+            (TokenType::Semicolon, 1, 10),
+            (TokenType::Ident(Cow::from("x")), 2, 3),
+            (TokenType::Eq, 2, 5),
+            (TokenType::String(Cow::from("a\nb")), 2, 8),
+            (TokenType::Ident(Cow::from("café")), 4, 5),
+            (TokenType::Ident(Cow::from("foo")), 6, 2),
+            (TokenType::EOF, 6, 5),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, line, column) in test_cases {
+            let tok = l.next_token();
+            assert_eq!((tok.kind, tok.line, tok.column), (kind, line, column));
+        }
+    }
+
+    #[test]
+    fn test_token_spans() {
+        let input = "let café = \"é${变量}\" ..= 0x_1F r#\"\\\"# @§ /* c */ ->\n\t1.5e3;  ";
+
+        // every span covers exactly the literal of its token
+        let tokens: Vec<Token> = Lexer::new(String::from(input)).collect();
+        for tok in &tokens[..tokens.len() - 1] {
+            assert_eq!(&input[tok.span()], tok.literal);
+        }
+        let eof = tokens.last().unwrap();
+        assert_eq!(eof.span(), input.len()..input.len());
+
+        let tok = Lexer::new(String::from("x /* open ✓")).nth(1).unwrap();
+        assert_eq!((tok.span(), tok.kind), (2.."x /* open ✓".len(), TokenType::UnterminatedComment(1)));
+    }
+
+    #[test]
+    fn test_truncated_input_does_not_panic() {
+        let input = "let s = \"a\\\"\" /* x /* y */ */ 0x1F_0 2.5e-3 café != <= // done";
+
+        // lex every prefix of the input, each must reach EOF and keep returning it
+        for (end, _) in input.char_indices().chain([(input.len(), ' ')]) {
+            let mut l = Lexer::new(input[..end].to_string());
+            let mut count = 0;
+            while l.next_token().kind != TokenType::EOF {
+                count += 1;
+                assert!(count <= end, "lexer did not reach EOF for {:?}", &input[..end]);
+            }
+            assert_eq!(l.next_token().kind, TokenType::EOF);
+            assert!(l.position <= l.input.len());
+        }
+    }
+
+    #[test]
+    fn test_lexer_iterator() {
+        let kinds: Vec<TokenType> = Lexer::new(String::from("x + 1")).map(|tok| tok.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Ident(Cow::from("x")),
+                TokenType::Plus,
+                TokenType::Int(Cow::from("1")),
+                TokenType::EOF,
+            ]
+        );
+
+        let mut l = Lexer::new(String::from("let"));
+        assert_eq!(l.by_ref().count(), 2);
+        assert!(l.next().is_none());
+        // the underlying lexer keeps returning EOF for callers using next_token directly
+        assert_eq!(l.next_token().kind, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_tab_aware_locations() {
+        let input = String::from("\tx\t= 1;\n  \t\"é\" y");
+
+        // (kind, line, column, display column, byte offset) with the default tab width of 4
+        let test_cases = vec![
+            (TokenType::Ident(Cow::from("x")), 1, 2, 5, 1),
+            (TokenType::Assign, 1, 4, 9, 3),
+            (TokenType::Int(Cow::from("1")), 1, 6, 11, 5),
+            (TokenType::Semicolon, 1, 7, 12, 6),
+            (TokenType::String(Cow::from("é")), 2, 4, 5, 11),
+            (TokenType::Ident(Cow::from("y")), 2, 8, 9, 16),
+        ];
+
+        let mut l = Lexer::new(input.clone());
+
+        for (kind, line, column, display_column, offset) in test_cases {
+            let tok = l.next_token();
+            assert_eq!(&input[tok.offset..tok.offset + tok.literal.len()], tok.literal);
+            assert_eq!(
+                (tok.kind, tok.line, tok.column, tok.display_column, tok.offset),
+                (kind, line, column, display_column, offset)
+            );
+        }
+
+        let mut l = Lexer::new(String::from("\tx"));
+        l.set_tab_width(8);
+        assert_eq!(l.next_token().display_column, 9);
+    }
+
+    #[test]
+    fn test_illegal_runs() {
+        let input = String::from("x @#$ y;@\n` @?#$?.z");
+
+        let test_cases = vec![
+            (TokenType::Ident(Cow::from("x")), "x", 1),
+            (TokenType::Illegal(String::from("illegal characters '@#$'")), "@#$", 3),
+            (TokenType::Ident(Cow::from("y")), "y", 7),
+            (TokenType::Semicolon, ";", 8),
+            (TokenType::Illegal(String::from("illegal character '@'")), "@", 9),
+            (TokenType::Illegal(String::from("illegal character '`'")), "`", 1),
+            // a '?' only ends the run where it starts a '?.' or '?['
+            (TokenType::Illegal(String::from("illegal characters '@?#$'")), "@?#$", 3),
+            (TokenType::SafeDot, "?.", 7),
+            (TokenType::Ident(Cow::from("z")), "z", 9),
+            (TokenType::EOF, "\0", 10),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, literal, column) in test_cases {
+            let tok = l.next_token();
+            assert_eq!((tok.kind, tok.literal.as_ref(), tok.column), (kind, literal, column));
+        }
+    }
+
+    #[test]
+    fn test_comments() {
+        let input = String::from(
+            r#"// a comment on its own line
+        let x = 10 / 2; // a trailing comment with "quotes" and @ symbols
+        //
+        /* a block comment */ x /* spanning
+        several lines with a // line comment inside */ * /* outer /* nested */ still a comment */ 2
+        x // the end"#,
+        );
+
+        let test_cases = vec![
+            TokenType::Let,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::Assign,
+            TokenType::Int(Cow::from("10")),
+            TokenType::Slash,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Semicolon,
+            TokenType::Ident(Cow::from("x")),
+            TokenType::Asterisk,
+            TokenType::Int(Cow::from("2")),
+            TokenType::Ident(Cow::from("x")),
             TokenType::EOF,
         ];
 
@@ -278,5 +1258,230 @@ mod tests {
             let tok = l.next_token();
             assert_eq!(tok.kind, test_case);
         }
+
+        let mut l = Lexer::new(String::from("x /* never /* closed */"));
+        assert_eq!(l.next_token().kind, TokenType::Ident(Cow::from("x")));
+        let tok = l.next_token();
+        assert_eq!(tok.kind, TokenType::UnterminatedComment(1));
+        assert_eq!(tok.literal, "/* never /* closed */");
+        assert_eq!(l.next_token().kind, TokenType::EOF);
+
+        let mut l = Lexer::new(String::from("/* a /* b"));
+        assert_eq!(l.next_token().kind, TokenType::UnterminatedComment(2));
+        assert_eq!(l.next_token().kind, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_string_literals() {
+        let input = String::from(r#""foo bar" "a\tb\nc" "say \"hi\"" "back\\slash" "\q" "" "open"#);
+
+        let test_cases = vec![
+            (TokenType::String(Cow::from("foo bar")), r#""foo bar""#),
+            (TokenType::String(Cow::from("a\tb\nc")), r#""a\tb\nc""#),
+            (TokenType::String(Cow::from("say \"hi\"")), r#""say \"hi\"""#),
+            (TokenType::String(Cow::from("back\\slash")), r#""back\\slash""#),
+            (TokenType::String(Cow::from("\\q")), r#""\q""#),
+            (TokenType::String(Cow::from("")), r#""""#),
+            (TokenType::UnterminatedString(String::from("open")), r#""open"#),
+            (TokenType::EOF, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, literal) in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, kind);
+            assert_eq!(tok.literal, literal);
+        }
+    }
+
+    #[test]
+    fn test_streaming_lexer() {
+        // long enough for the consumed input to be dropped several times along the way
+        let line = "let café = r#\"a \"raw\" string\"# + \"${x.len()}\" /* note */ ** 0x_ff;\n\t1..=2 @@ 3.5e-1;\n";
+        let input = line.repeat(500);
+
+        let expected: Vec<Token> = Lexer::new(input.clone()).collect();
+        let chars: Vec<char> = input.chars().collect();
+        let streamed: Vec<Token> = Lexer::from_chars(chars.into_iter()).collect();
+        assert_eq!(streamed, expected);
+
+        // sources can be borrowed, and the lexer can be sent to another thread along with them
+        let borrowed: Vec<Token> = Lexer::from_chars(input.chars()).collect();
+        assert_eq!(borrowed, expected);
+        let mut bytes = input.as_bytes();
+        let sent = std::thread::scope(|scope| {
+            let l = Lexer::from_reader(&mut bytes);
+            scope.spawn(move || l.collect::<Vec<Token>>()).join().unwrap()
+        });
+        assert_eq!(sent, expected);
+
+        let mut l = Lexer::from_reader(io::Cursor::new(input.into_bytes()));
+        let read: Vec<Token> = l.by_ref().collect();
+        assert_eq!(read, expected);
+        assert!(l.io_error().is_none());
+        // only a window of the input is kept around
+        assert!(l.input.len() < COMPACT_AFTER + line.len() + LOOKAHEAD_BYTES);
+    }
+
+    #[test]
+    fn test_borrowed_input() {
+        let input = "let café = \"plain\" + \"esc\\n\" + r\"raw\" + 1_000; // note";
+        let borrowed = |text: &Cow<str>| matches!(text, Cow::Borrowed(_));
+
+        // a &str input is sliced rather than copied, except for values that had to be rewritten
+        let tokens: Vec<TokenWithTrivia> = {
+            let mut l = Lexer::new(input);
+            std::iter::from_fn(|| Some(l.next_token_with_trivia())).take(10).collect()
+        };
+        for tok in &tokens {
+            assert!(borrowed(&tok.token.literal), "{:?} was copied", tok.token);
+            assert!(tok.leading_trivia.iter().all(|t| borrowed(&t.text)));
+        }
+        let values: Vec<bool> = tokens
+            .iter()
+            .filter_map(|tok| match &tok.token.kind {
+                TokenType::Ident(v) | TokenType::String(v) | TokenType::Int(v) => Some(borrowed(v)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(values, vec![true, true, false, true, false]);
+        assert_eq!(tokens[3].token.kind, TokenType::String(Cow::from("plain")));
+        assert_eq!(tokens[5].token.kind, TokenType::String(Cow::from("esc\n")));
+        assert_eq!(tokens[9].token.kind, TokenType::Int(Cow::from("1000")));
+
+        // an owned input hands out owned copies of its text
+        let tok = Lexer::new(input.to_string()).nth(1).unwrap();
+        assert_eq!(tok.kind, TokenType::Ident(Cow::from("café")));
+        assert!(!borrowed(&tok.literal));
+    }
+
+    #[test]
+    fn test_trivia() {
+        let input = "  // header\nlet x = 5; /* a /* nested */ note */\n\tx // trailing\n/* open";
+        let mut l = Lexer::new(String::from(input));
+        let mut tokens = Vec::new();
+        loop {
+            let tok = l.next_token_with_trivia();
+            let done = matches!(tok.token.kind, TokenType::EOF | TokenType::UnterminatedComment(_));
+            tokens.push(tok);
+            if done {
+                break;
+            }
+        }
+
+        let first = &tokens[0].leading_trivia;
+        assert_eq!(
+            first.iter().map(|t| (t.kind, t.text.as_ref())).collect::<Vec<_>>(),
+            vec![(TriviaKind::Whitespace, "  "), (TriviaKind::LineComment, "// header"), (TriviaKind::Whitespace, "\n")]
+        );
+        assert_eq!(first[1].location, Location::new(1, 3, 3, 2));
+        assert_eq!(tokens[0].token.kind, TokenType::Let);
+        assert!(tokens[1].leading_trivia.iter().all(|t| t.kind == TriviaKind::Whitespace));
+        assert_eq!(tokens[5].leading_trivia[1].text, "/* a /* nested */ note */");
+
+        // nothing is lost, the trivia and literals add back up to the input
+        let rebuilt: String = tokens
+            .iter()
+            .flat_map(|tok| tok.leading_trivia.iter().map(|t| t.text.as_ref()).chain([tok.token.literal.as_ref()]))
+            .collect();
+        assert_eq!(rebuilt, input);
+
+        // an input that ends normally rebuilds exactly too, with nothing added for the EOF token
+        for input in ["let x = 5; // hi\n", "x", ""] {
+            let mut l = Lexer::new(String::from(input));
+            let mut rebuilt = String::new();
+            loop {
+                let tok = l.next_token_with_trivia();
+                rebuilt.extend(tok.leading_trivia.iter().map(|t| t.text.as_ref()));
+                rebuilt.push_str(&tok.token.literal);
+                if tok.token.kind == TokenType::EOF {
+                    assert_eq!(tok.token.literal, "");
+                    break;
+                }
+            }
+            assert_eq!(rebuilt, input);
+        }
+    }
+
+    #[test]
+    fn test_string_interpolation() {
+        let input = String::from(r#""hello ${name}!" "${ {"a": x}["a"] }" "\${x} $x" "${"in ${y}"}" "${x"#);
+
+        let kinds = |tokens: &[Token<'static>]| tokens.iter().map(|tok| tok.kind.clone()).collect::<Vec<_>>();
+        let ident = |name: &'static str| TokenType::Ident(Cow::from(name));
+        let mut l = Lexer::new(input);
+
+        let tok = l.next_token();
+        assert_eq!(tok.literal, r#""hello ${name}!""#);
+        let TokenType::InterpolatedString(parts) = tok.kind else { panic!("expected an interpolated string") };
+        assert_eq!(parts.len(), 3);
+        assert_eq!(parts[0], StringPart::Text(Cow::from("hello ")));
+        // embedded tokens keep their position in the input
+        assert_eq!(parts[1], StringPart::Expr(vec![{
+            let mut tok = Token::new(ident("name"), "name");
+            tok.set_location(Location::new(1, 10, 10, 9));
+            tok.set_end_offset(13);
+            tok
+        }]));
+        assert_eq!(parts[2], StringPart::Text(Cow::from("!")));
+
+        // braces inside the expression are balanced before the closing one
+        let TokenType::InterpolatedString(parts) = l.next_token().kind else { panic!("expected an interpolated string") };
+        let StringPart::Expr(tokens) = &parts[0] else { panic!("expected an expression") };
+        assert_eq!(
+            kinds(tokens),
+            vec![
+                TokenType::LBrace,
+                TokenType::String(Cow::from("a")),
+                TokenType::Colon,
+                ident("x"),
+                TokenType::RBrace,
+                TokenType::LBracket,
+                TokenType::String(Cow::from("a")),
+                TokenType::RBracket,
+            ]
+        );
+
+        // an escaped or lone $ is plain text
+        assert_eq!(l.next_token().kind, TokenType::String(Cow::from("${x} $x")));
+
+        // strings inside an expression can be interpolated too
+        let TokenType::InterpolatedString(parts) = l.next_token().kind else { panic!("expected an interpolated string") };
+        let StringPart::Expr(tokens) = &parts[0] else { panic!("expected an expression") };
+        let TokenType::InterpolatedString(inner) = &tokens[0].kind else { panic!("expected an interpolated string") };
+        assert_eq!(inner[0], StringPart::Text(Cow::from("in ")));
+
+        let tok = l.next_token();
+        assert_eq!(tok.kind, TokenType::UnterminatedString(String::new()));
+        assert_eq!(tok.literal, r#""${x"#);
+        assert_eq!(l.next_token().kind, TokenType::EOF);
+    }
+
+    #[test]
+    fn test_raw_string_literals() {
+        let input = String::from(r###"r"\d+\n" r#"say "hi""# r"" r "x" r#x r##"a"#b"## r#"open"###);
+
+        let test_cases = vec![
+            (TokenType::String(Cow::from(r"\d+\n")), r#"r"\d+\n""#),
+            (TokenType::String(Cow::from(r#"say "hi""#)), r##"r#"say "hi""#"##),
+            (TokenType::String(Cow::from("")), r#"r"""#),
+            (TokenType::Ident(Cow::from("r")), "r"),
+            (TokenType::String(Cow::from("x")), r#""x""#),
+            (TokenType::Ident(Cow::from("r")), "r"),
+            (TokenType::Illegal(String::from("illegal character '#'")), "#"),
+            (TokenType::Ident(Cow::from("x")), "x"),
+            (TokenType::String(Cow::from(r##"a"#b"##)), r###"r##"a"#b"##"###),
+            (TokenType::UnterminatedString(String::from("open")), r##"r#"open"##),
+            (TokenType::EOF, "\0"),
+        ];
+
+        let mut l = Lexer::new(input);
+
+        for (kind, literal) in test_cases {
+            let tok = l.next_token();
+            assert_eq!(tok.kind, kind);
+            assert_eq!(tok.literal, literal);
+        }
     }
 }
@@ -1,4 +1,6 @@
+pub mod diagnostic;
 pub mod lexer;
 pub mod token;
 pub mod utils;
-pub mod repl;
\ No newline at end of file
+pub mod repl;
+pub mod source_map;
\ No newline at end of file
@@ -1,9 +1,119 @@
-mod token;
-mod repl;
-mod lexer;
-mod utils;
+use std::env;
+use std::fs;
+use std::io::{self, BufReader, Read, Write};
+use std::process;
+
+use nipl_interpreter::diagnostic::diagnostic;
+use nipl_interpreter::lexer::diff;
+use nipl_interpreter::lexer::lexer::Lexer;
+use nipl_interpreter::repl;
+use nipl_interpreter::repl::repl::{Config, OutputMode};
+
+const USAGE: &str = "usage: nipl [repl] [--max-output <lines>] [--max-output-bytes <bytes>]
+                   [--script <session.txt> [--expect <transcript.txt>]]
+                   [--error-format <human|json>] [--output <plain|json|quiet>] [--tab-width <columns>]
+       nipl lex <file.nipl | ->
+       nipl lex --compare <old.nipl> <new.nipl>
+       nipl --version";
+
+// ReplArgs holds the flags accepted when starting the REPL
+#[derive(Default)]
+struct ReplArgs {
+    config: Config,
+    script: Option<String>,
+    expect: Option<String>,
+}
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    match args.iter().map(String::as_str).collect::<Vec<_>>().as_slice() {
+        ["--version" | "-V"] => print_version(),
+        ["lex", "--compare", old, new] => process::exit(compare(old, new)),
+        ["lex", path] => process::exit(lex(path)),
+        ["repl", flags @ ..] | flags => match parse_repl_args(flags) {
+            Ok(ReplArgs { config, script: Some(script), expect }) => {
+                process::exit(run_script(&script, expect.as_deref(), config))
+            }
+            Ok(ReplArgs { expect: Some(_), .. }) => usage_error("--expect needs --script"),
+            Ok(args) => start_repl(args.config),
+            Err(err) => usage_error(&err),
+        },
+    }
+}
+
+fn usage_error(err: &str) -> ! {
+    eprintln!("error: {err}\n{USAGE}");
+    process::exit(2);
+}
+
+/// parse_repl_args reads the flags accepted by the REPL
+fn parse_repl_args(flags: &[&str]) -> Result<ReplArgs, String> {
+    let mut args = ReplArgs::default();
+    args.config.color = diagnostic::use_color();
+    args.config.editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).ok();
+    // accept both "--flag value" and "--flag=value"
+    let flags: Vec<&str> = flags.iter().flat_map(|flag| match flag.split_once('=') {
+        Some((flag, value)) if flag.starts_with("--") => vec![flag, value],
+        _ => vec![*flag],
+    }).collect();
+    let mut flags = flags.iter();
+    while let Some(flag) = flags.next() {
+        let value = match *flag {
+            "--max-output" | "--max-output-bytes" | "--script" | "--expect" | "--error-format" | "--output" | "--tab-width" => {
+                flags.next().ok_or(format!("{flag} needs a value"))?
+            }
+            _ => return Err(format!("unknown argument '{flag}'")),
+        };
+        let parse_count = || value.parse().map_err(|_| format!("invalid value '{value}' for {flag}"));
+        match *flag {
+            "--max-output" => args.config.limit.max_lines = Some(parse_count()?),
+            "--max-output-bytes" => args.config.limit.max_bytes = Some(parse_count()?),
+            "--error-format" => args.config.error_format = value.parse()?,
+            "--output" => args.config.output = value.parse()?,
+            "--tab-width" => args.config.tab_width = parse_count()?,
+            "--script" => args.script = Some(value.to_string()),
+            _ => args.expect = Some(value.to_string()),
+        }
+    }
+    Ok(args)
+}
+
+/// run_script feeds a session file through the REPL and echoes the transcript. When an
+/// expected transcript is given the output is compared against it instead, returning 1 on mismatch
+fn run_script(script: &str, expect: Option<&str>, mut config: Config) -> i32 {
+    let file = fs::File::open(script).unwrap_or_else(|err| {
+        eprintln!("error: could not read {script}: {err}");
+        process::exit(2);
+    });
+
+    config.echo = true;
+    let Some(expect) = expect else {
+        repl::repl::run(BufReader::new(file), io::stdout(), &config).expect("failed to run repl");
+        return 0;
+    };
+
+    // transcripts are compared as plain text
+    config.color = false;
+    let mut transcript = Vec::new();
+    repl::repl::run(BufReader::new(file), &mut transcript, &config).expect("failed to run repl");
+    let transcript = String::from_utf8_lossy(&transcript);
+    match repl::repl::first_mismatch(&transcript, &read_source(expect)) {
+        None => {
+            println!("transcript matches {expect}");
+            0
+        }
+        Some((line, actual, expected)) => {
+            println!("transcript differs from {expect} at line {line}\n  expected: {expected}\n    actual: {actual}");
+            1
+        }
+    }
+}
+
+fn start_repl(config: Config) {
+    // only people at a terminal want the banner
+    if config.output != OutputMode::Plain {
+        return repl::repl::start(config);
+    }
     println!("==============================Starting REPL==============================");
     println!(r"
     Welcome to the nipl repl...
@@ -12,5 +122,72 @@ fn main() {
     ");
     println!("========================All rights reserved. 2023=========================");
 
-    repl::repl::start();
-}
\ No newline at end of file
+    repl::repl::start(config);
+}
+
+/// print_version prints the interpreter version along with how it was built
+fn print_version() {
+    println!("nipl {}", env!("CARGO_PKG_VERSION"));
+    println!("profile: {}", if cfg!(debug_assertions) { "debug" } else { "release" });
+    println!("target: {}-{}", env::consts::ARCH, env::consts::OS);
+    // no optional cargo features exist yet, list them here as they are added
+    println!("features: none");
+}
+
+/// read_source reads a source file or exits with an error message if it cannot be read
+fn read_source(path: &str) -> String {
+    fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("error: could not read {path}: {err}");
+        process::exit(2);
+    })
+}
+
+/// lex prints the tokens of a file, or of stdin for "-", as they are read without loading the whole
+/// input first, and returns the exit code
+fn lex(path: &str) -> i32 {
+    let reader: Box<dyn Read> = match path {
+        "-" => Box::new(io::stdin().lock()),
+        _ => match fs::File::open(path) {
+            Ok(file) => Box::new(file),
+            Err(err) => {
+                eprintln!("error: could not read {path}: {err}");
+                return 2;
+            }
+        },
+    };
+    let mut lexer = Lexer::from_reader(reader);
+
+    let mut out = io::BufWriter::new(io::stdout().lock());
+    for tok in lexer.by_ref() {
+        if writeln!(out, "{tok:?}").is_err() {
+            return 1;
+        }
+    }
+    match lexer.io_error() {
+        Some(err) => {
+            eprintln!("error: could not read {path}: {err}");
+            2
+        }
+        None => 0,
+    }
+}
+
+/// compare prints the token-level changes between two files and returns the exit code,
+/// 0 when the token streams are identical and 1 otherwise
+fn compare(old_path: &str, new_path: &str) -> i32 {
+    let (old_source, new_source) = (read_source(old_path), read_source(new_path));
+    let old = diff::tokenize(old_source.as_str());
+    let new = diff::tokenize(new_source.as_str());
+
+    let changes = diff::diff_tokens(&old, &new);
+    if changes.is_empty() {
+        println!("no token changes between {old_path} and {new_path}");
+        return 0;
+    }
+
+    println!("--- {old_path}\n+++ {new_path}");
+    for change in &changes {
+        println!("{change}");
+    }
+    1
+}
@@ -1 +1,3 @@
-pub mod repl;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod repl;
+pub mod output;
@@ -0,0 +1,131 @@
+use std::io::{self, Write};
+
+const TRUNCATED_MARKER: &str = "... output truncated";
+
+// OutputLimit caps how much a single evaluation may print, None means unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct OutputLimit {
+    pub max_bytes: Option<usize>,
+    pub max_lines: Option<usize>,
+}
+
+// LimitedWriter forwards writes to the inner writer until the limit is hit, then prints
+// a truncation marker once and silently drops everything else until it is reset
+pub struct LimitedWriter<W: Write> {
+    inner: W,
+    limit: OutputLimit,
+    bytes: usize,
+    lines: usize,
+    last_byte: Option<u8>,
+    truncated: bool,
+}
+
+impl<W: Write> LimitedWriter<W> {
+    pub fn new(inner: W, limit: OutputLimit) -> Self {
+        Self {
+            inner,
+            limit,
+            bytes: 0,
+            lines: 0,
+            last_byte: None,
+            truncated: false,
+        }
+    }
+
+    /// get_mut returns the inner writer for output that should bypass the limit
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// reset clears the counters so the next evaluation gets the full limit again
+    pub fn reset(&mut self) {
+        self.bytes = 0;
+        self.lines = 0;
+        self.last_byte = None;
+        self.truncated = false;
+    }
+
+    /// is_full returns true if writing another byte would exceed the limit
+    fn is_full(&self) -> bool {
+        self.limit.max_bytes.is_some_and(|max| self.bytes >= max)
+            || self.limit.max_lines.is_some_and(|max| self.lines >= max)
+    }
+}
+
+impl<W: Write> Write for LimitedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.truncated {
+            return Ok(buf.len());
+        }
+
+        // count how much of the buffer still fits within the limit
+        let mut end = 0;
+        while end < buf.len() && !self.is_full() {
+            self.bytes += 1;
+            if buf[end] == b'\n' {
+                self.lines += 1;
+            }
+            end += 1;
+        }
+        // never cut a multi-byte char in half, drop the whole char instead. The bytes given back are
+        // part of that char so none of them is a new line
+        while end > 0 && end < buf.len() && buf[end] & 0xC0 == 0x80 {
+            end -= 1;
+            self.bytes -= 1;
+        }
+        self.inner.write_all(&buf[..end])?;
+        if end > 0 {
+            self.last_byte = Some(buf[end - 1]);
+        }
+
+        if end < buf.len() {
+            self.truncated = true;
+            if self.last_byte.is_some_and(|b| b != b'\n') {
+                writeln!(self.inner)?;
+            }
+            writeln!(self.inner, "{TRUNCATED_MARKER}")?;
+        }
+        // report everything as written so callers don't retry the dropped bytes
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limited_writer() {
+        let limit = OutputLimit { max_bytes: None, max_lines: Some(2) };
+        let mut w = LimitedWriter::new(Vec::new(), limit);
+        for i in 0..5 {
+            writeln!(w, "line {i}").unwrap();
+        }
+        assert_eq!(String::from_utf8(w.inner.clone()).unwrap(), "line 0\nline 1\n... output truncated\n");
+
+        w.reset();
+        w.inner.clear();
+        writeln!(w, "fresh").unwrap();
+        assert_eq!(String::from_utf8(w.inner.clone()).unwrap(), "fresh\n");
+
+        let limit = OutputLimit { max_bytes: Some(4), max_lines: None };
+        let mut w = LimitedWriter::new(Vec::new(), limit);
+        write!(w, "abcdefgh").unwrap();
+        assert_eq!(String::from_utf8(w.inner).unwrap(), "abcd\n... output truncated\n");
+
+        // a limit inside a multi-byte char cuts before the char
+        let limit = OutputLimit { max_bytes: Some(1), max_lines: None };
+        let mut w = LimitedWriter::new(Vec::new(), limit);
+        write!(w, "é").unwrap();
+        assert_eq!(String::from_utf8(w.inner).unwrap(), "... output truncated\n");
+
+        let limit = OutputLimit { max_bytes: Some(5), max_lines: None };
+        let mut w = LimitedWriter::new(Vec::new(), limit);
+        write!(w, "ab😀cd").unwrap();
+        assert_eq!(String::from_utf8(w.inner).unwrap(), "ab\n... output truncated\n");
+    }
+}
@@ -1,32 +1,522 @@
+use std::fs::{self, OpenOptions};
 use std::io;
-use std::io::Write;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use crate::diagnostic::diagnostic::{Diagnostic, ErrorFormat};
 use crate::lexer::lexer::Lexer;
-use crate::token::token::TokenType;
+use crate::repl::output::{LimitedWriter, OutputLimit};
+use crate::token::token::{StringPart, Token, TokenType};
+use crate::utils::{json_string, DEFAULT_TAB_WIDTH};
 
 const PROMPT: &str = ">> ";
 
-pub fn start() {
+// OutputMode selects what the REPL writes for every input
+#[derive(Debug, Default, PartialEq, Clone, Copy)]
+pub enum OutputMode {
+    #[default]
+    Plain, // prompts, tokens and diagnostics for people at a terminal
+    Json,  // one JSON object per input and no prompts, for wrappers driving the REPL
+    Quiet, // only diagnostics
+}
+
+impl std::str::FromStr for OutputMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "plain" => Ok(OutputMode::Plain),
+            "json" => Ok(OutputMode::Json),
+            "quiet" => Ok(OutputMode::Quiet),
+            _ => Err(format!("unknown output mode '{s}', expected 'plain', 'json' or 'quiet'")),
+        }
+    }
+}
+
+// Config holds the settings a REPL session runs with
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub limit: OutputLimit,
+    pub echo: bool,  // write every input after its prompt so the output reads as a transcript
+    pub color: bool, // color diagnostics with ANSI escapes
+    pub error_format: ErrorFormat,
+    pub editor: Option<String>, // command used by ':edit', usually $VISUAL or $EDITOR
+    pub output: OutputMode,
+    pub tab_width: usize, // columns between tab stops, shared by token display columns and diagnostics
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            limit: OutputLimit::default(),
+            echo: false,
+            color: false,
+            error_format: ErrorFormat::default(),
+            editor: None,
+            output: OutputMode::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+        }
+    }
+}
+
+pub fn start(config: Config) {
+    run(io::stdin().lock(), io::stdout(), &config).expect("failed to run repl");
+}
+
+/// run drives the REPL over any input and output until an empty line or the end of input
+pub fn run<R: BufRead, W: Write>(mut input: R, output: W, config: &Config) -> io::Result<()> {
+    // everything an input produces goes through the limiter so runaway output is cut short
+    let mut out = LimitedWriter::new(output, config.limit);
+    // every processed input, numbered from 1 in the prompt
+    let mut history: Vec<String> = Vec::new();
+
     loop {
-        print!("{PROMPT}");
+        // the prompt and the echoed input don't count towards the output limit
+        if config.output == OutputMode::Plain {
+            write!(out.get_mut(), "[{}] {PROMPT}", history.len() + 1)?;
+        }
         // don't wait for a new line, flush the present print job to the output
-        io::stdout().flush().unwrap();
+        out.flush()?;
 
-        let mut input = String::new();
-        // read the input line and panic if error in reading
-        io::stdin().read_line(&mut input).expect("failed to read input");
+        let mut line = String::new();
+        // stop at the end of the input
+        if input.read_line(&mut line)? == 0 { return Ok(()); }
+        echo_input(&mut out, &line, config)?;
 
         // using the ENTER key as the terminator
-        if input == "\n" { return; }
+        if line == "\n" { return Ok(()); }
+
+        // give every input its own output budget
+        out.reset();
+
+        let started = Instant::now();
+        let source = match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            [":paste"] => read_paste(&mut input, &mut out, config)?,
+            [":edit", rest @ ..] => match edit(&history, rest.first().copied(), config) {
+                Ok(source) => {
+                    // show what is about to be processed since it never went through the prompt
+                    if config.output == OutputMode::Plain {
+                        write!(out.get_mut(), "{source}")?;
+                    }
+                    source
+                }
+                Err(err) => {
+                    let diagnostic = Diagnostic::error(err);
+                    match config.output {
+                        // nothing was added to the history, so the record has no input number
+                        OutputMode::Json => {
+                            let diagnostics = diagnostic.to_json("", "repl", config.tab_width);
+                            write_record(&mut out, None, "", "error", &diagnostics, started.elapsed())?;
+                        }
+                        _ => write!(out, "{}", diagnostic.emit("", "repl", config.error_format, config.color, config.tab_width))?,
+                    }
+                    continue;
+                }
+            },
+            _ => line,
+        };
+
+        history.push(source);
+        eval(&history, &mut out, config)?;
+    }
+}
+
+/// echo_input writes the input back out when running as a transcript
+fn echo_input<W: Write>(out: &mut LimitedWriter<W>, line: &str, config: &Config) -> io::Result<()> {
+    if config.echo && config.output == OutputMode::Plain {
+        write!(out.get_mut(), "{line}")?;
+        if !line.ends_with('\n') { writeln!(out.get_mut())?; }
+    }
+    Ok(())
+}
+
+/// read_paste collects raw lines until a lone ':end' or the end of input so pasted code is
+/// processed as a single blob instead of line by line
+fn read_paste<R: BufRead, W: Write>(input: &mut R, out: &mut LimitedWriter<W>, config: &Config) -> io::Result<String> {
+    if config.output == OutputMode::Plain {
+        writeln!(out.get_mut(), "// entering paste mode, finish with ':end' or Ctrl-D")?;
+        out.flush()?;
+    }
+
+    let mut source = String::new();
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 { break; }
+        echo_input(out, &line, config)?;
+        if line.trim_end() == ":end" { break; }
+        source.push_str(&line);
+    }
+    Ok(source)
+}
+
+/// edit opens a previous input (the last one by default) in the editor and returns the saved text
+fn edit(history: &[String], number: Option<&str>, config: &Config) -> Result<String, String> {
+    let index = match number {
+        None => history.len().checked_sub(1).ok_or("there is no previous input to edit")?,
+        Some(n) => match n.parse::<usize>() {
+            Ok(n) if (1..=history.len()).contains(&n) => n - 1,
+            _ => return Err(format!("there is no input numbered {n}")),
+        },
+    };
+    let editor = config.editor.as_deref().ok_or("no editor configured, set $EDITOR")?;
+
+    let path = create_edit_file(&history[index]).map_err(|err| format!("could not create a file to edit: {err}"))?;
+
+    // run through the shell so editors configured with arguments, e.g. "code --wait", work
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{editor} \"$1\""))
+        .arg("sh")
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path).map_err(|err| format!("could not read {}: {err}", path.display()));
+    // the file holds the user's input, it is removed whether or not the editor could run
+    let _ = fs::remove_file(&path);
+
+    let status = status.map_err(|err| format!("could not start {editor}: {err}"))?;
+    match status.success() {
+        true => edited,
+        false => Err(format!("{editor} exited with {status}, input discarded")),
+    }
+}
+
+/// create_edit_file writes contents to a new file in the temp directory and returns its path. The file
+/// is created exclusively with a hard to guess name, so an existing file or a symlink planted under the
+/// name is never written through, and a clash just moves on to another name
+fn create_edit_file(contents: &str) -> io::Result<PathBuf> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    let mut last_err = None;
+    for attempt in 0..16u32 {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.subsec_nanos());
+        let name = format!("nipl-edit-{}-{:08x}{:x}.nipl", std::process::id(), nanos, attempt);
+        let path = std::env::temp_dir().join(name);
+        match options.open(&path) {
+            Ok(mut file) => {
+                if let Err(err) = file.write_all(contents.as_bytes()) {
+                    let _ = fs::remove_file(&path);
+                    return Err(err);
+                }
+                return Ok(path);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => last_err = Some(err),
+            Err(err) => return Err(err),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::from(io::ErrorKind::AlreadyExists)))
+}
+
+/// eval lexes the latest input in the history and writes out its tokens along with any diagnostics
+fn eval<W: Write>(history: &[String], out: &mut LimitedWriter<W>, config: &Config) -> io::Result<()> {
+    let source = history.last().map_or("", String::as_str);
+    let started = Instant::now();
+    // start a lexer and read tokens until the end of the input
+    let mut lexer = Lexer::new(source);
+    lexer.set_tab_width(config.tab_width);
+    let tokens: Vec<Token> = lexer.take_while(|tok| tok.kind != TokenType::EOF).collect();
+    let elapsed = started.elapsed();
+
+    if config.output == OutputMode::Json {
+        let result = tokens.iter().map(token_json).collect::<Vec<_>>().join(",");
+        let diagnostics = tokens
+            .iter()
+            .flat_map(|tok| diagnose(tok, source))
+            .map(|diagnostic| diagnostic.to_json(source, "repl", config.tab_width))
+            .collect::<Vec<_>>()
+            .join(",");
+        return write_record(out, Some(history.len()), &result, "tokens", &diagnostics, elapsed);
+    }
+
+    for tok in &tokens {
+        if config.output == OutputMode::Plain {
+            writeln!(out, "{tok:?}")?;
+        }
+        for diagnostic in diagnose(tok, source) {
+            write!(out, "{}", diagnostic.emit(source, "repl", config.error_format, config.color, config.tab_width))?;
+        }
+    }
+    Ok(())
+}
 
-        // start a lexer
-        let mut lex = Lexer::new(input);
-        // read the next token
-        let mut tok = lex.next_token();
-        // while there are tokens to read
-        while tok.kind != TokenType::EOF {
-            println!("{tok:?}");
-            // read the next token
-            tok = lex.next_token();
+/// closing_offset returns where the closing delimiter of a token running to the end of the source goes:
+/// its end, but before the line break that ends the last line
+fn closing_offset(tok: &Token, source: &str) -> usize {
+    let text = &source[tok.offset..tok.end_offset];
+    let line_break = match text {
+        _ if text.ends_with("\r\n") => 2,
+        _ if text.ends_with('\n') => 1,
+        _ => 0,
+    };
+    tok.end_offset - line_break
+}
+
+/// write_record writes the JSON object describing one input, with result and diagnostics holding the
+/// already formatted elements of their arrays. input is null for a command that failed without adding
+/// an input to the history
+fn write_record<W: Write>(
+    out: &mut LimitedWriter<W>,
+    input: Option<usize>,
+    result: &str,
+    kind: &str,
+    diagnostics: &str,
+    elapsed: Duration,
+) -> io::Result<()> {
+    // a truncated object is worse than a long one, so the record bypasses the output limit.
+    // Nothing can print yet, so stdout is always empty until there is an evaluator
+    let input = input.map_or(String::from("null"), |n| n.to_string());
+    writeln!(
+        out.get_mut(),
+        r#"{{"input":{input},"result":[{result}],"type":"{kind}","stdout":"","diagnostics":[{diagnostics}],"elapsed_us":{}}}"#,
+        elapsed.as_micros(),
+    )?;
+    out.flush()
+}
+
+/// diagnose returns the diagnostics for a token that signals a lexing error, including the errors in the
+/// expressions of an interpolated string
+fn diagnose(tok: &Token, source: &str) -> Vec<Diagnostic> {
+    match &tok.kind {
+        TokenType::InterpolatedString(parts) => parts
+            .iter()
+            .flat_map(|part| match part {
+                StringPart::Expr(tokens) => tokens.iter().flat_map(|tok| diagnose(tok, source)).collect(),
+                _ => Vec::new(),
+            })
+            .collect(),
+        _ => diagnose_token(tok, source).into_iter().collect(),
+    }
+}
+
+/// diagnose_token returns the diagnostic for a single token that signals a lexing error
+fn diagnose_token(tok: &Token, source: &str) -> Option<Diagnostic> {
+    match tok.kind {
+        TokenType::Illegal(ref message) => {
+            Some(Diagnostic::error(message)
+                .with_code("E0001")
+                .with_label(tok.span(), "not a valid token"))
+        }
+        TokenType::UnterminatedString(_) => {
+            // an unterminated string runs to the end of the source. The label leaves out trailing
+            // whitespace, but the quote goes after trailing spaces since they are part of the string
+            let label_end = source[..tok.end_offset].trim_end().len().max(tok.offset + 1);
+            let end = closing_offset(tok, source);
+            Some(Diagnostic::error("unterminated string literal")
+                .with_code("E0002")
+                .with_label(tok.offset..label_end, "this string is never closed")
+                .with_suggestion(end..end, "\"", "close the string"))
+        }
+        TokenType::UnterminatedComment(depth) => {
+            // the comment runs to the end of the source too, every nested comment still open needs closing
+            let end = closing_offset(tok, source);
+            Some(Diagnostic::error("unterminated block comment")
+                .with_code("E0003")
+                .with_label(tok.offset..tok.offset + 2, "this comment is never closed")
+                .with_suggestion(end..end, " */".repeat(depth), "close the comment"))
+        }
+        _ => None,
+    }
+}
+
+/// token_json formats a token as a JSON object
+fn token_json(tok: &Token) -> String {
+    format!(
+        r#"{{"kind":{},"literal":{},"line":{},"column":{},"offset":{},"end_offset":{}}}"#,
+        json_string(&format!("{:?}", tok.kind)),
+        json_string(&tok.literal),
+        tok.line,
+        tok.column,
+        tok.offset,
+        tok.end_offset,
+    )
+}
+
+/// first_mismatch compares two transcripts line by line, ignoring trailing whitespace,
+/// and returns the 1-based line number and both lines of the first difference
+pub fn first_mismatch<'a>(actual: &'a str, expected: &'a str) -> Option<(usize, &'a str, &'a str)> {
+    let mut actual_lines = actual.trim_end().lines();
+    let mut expected_lines = expected.trim_end().lines();
+    let mut line_no = 1;
+    loop {
+        match (actual_lines.next(), expected_lines.next()) {
+            (None, None) => return None,
+            (a, e) if a.map(str::trim_end) == e.map(str::trim_end) => line_no += 1,
+            (a, e) => return Some((line_no, a.unwrap_or("<end of transcript>"), e.unwrap_or("<end of transcript>"))),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_scripted_session() {
+        let script = "let x = 5;\n!=\nx @ 1\n\nignored after the empty line\n";
+        let mut transcript = Vec::new();
+        let config = Config { echo: true, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+
+        let expected = r#"[1] >> let x = 5;
+Token { kind: Let, literal: "let", line: 1, column: 1, display_column: 1, offset: 0, end_offset: 3 }
+Token { kind: Ident("x"), literal: "x", line: 1, column: 5, display_column: 5, offset: 4, end_offset: 5 }
+Token { kind: Assign, literal: "=", line: 1, column: 7, display_column: 7, offset: 6, end_offset: 7 }
+Token { kind: Int("5"), literal: "5", line: 1, column: 9, display_column: 9, offset: 8, end_offset: 9 }
+Token { kind: Semicolon, literal: ";", line: 1, column: 10, display_column: 10, offset: 9, end_offset: 10 }
+[2] >> !=
+Token { kind: NotEq, literal: "!=", line: 1, column: 1, display_column: 1, offset: 0, end_offset: 2 }
+[3] >> x @ 1
+Token { kind: Ident("x"), literal: "x", line: 1, column: 1, display_column: 1, offset: 0, end_offset: 1 }
+Token { kind: Illegal("illegal character '@'"), literal: "@", line: 1, column: 3, display_column: 3, offset: 2, end_offset: 3 }
+error[E0001]: illegal character '@'
+ --> repl:1:3
+  |
+1 | x @ 1
+  |   ^ not a valid token
+Token { kind: Int("1"), literal: "1", line: 1, column: 5, display_column: 5, offset: 4, end_offset: 5 }
+[4] >> 
+"#;
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert_eq!(first_mismatch(&transcript, expected), None);
+        assert_eq!(first_mismatch(&transcript, "[1] >> let x = 6;"), Some((1, "[1] >> let x = 5;", "[1] >> let x = 6;")));
+    }
+
+    #[test]
+    fn test_paste_mode() {
+        let script = ":paste\nlet f = fn(x) {\n\n  x @ 1\n};\n:end\n";
+        let mut transcript = Vec::new();
+        let config = Config { echo: true, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+
+        let transcript = String::from_utf8(transcript).unwrap();
+        // the empty line inside the paste doesn't end the session and the diagnostic points into the blob
+        assert!(transcript.starts_with("[1] >> :paste\n// entering paste mode, finish with ':end' or Ctrl-D\nlet f = fn(x) {\n"));
+        assert!(transcript.contains(" --> repl:3:5\n"));
+        assert!(transcript.ends_with("Token { kind: Semicolon, literal: \";\", line: 4, column: 2, display_column: 2, offset: 26, end_offset: 27 }\n[2] >> "));
+    }
+
+    #[test]
+    fn test_edit_previous_input() {
+        let script = "let x = 1;\n:edit 1\n:edit 5\n";
+        let mut transcript = Vec::new();
+        // an "editor" that overwrites the file, portable unlike sed -i
+        let editor = String::from("sh -c 'printf \"let x = 2;\\n\" > \"$1\"' sh");
+        let config = Config { echo: true, editor: Some(editor), ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.contains("[2] >> :edit 1\nlet x = 2;\nToken { kind: Let"));
+        assert!(transcript.contains("Token { kind: Int(\"2\"), literal: \"2\", line: 1, column: 9, display_column: 9, offset: 8, end_offset: 9 }"));
+        assert!(transcript.ends_with("[3] >> :edit 5\nerror: there is no input numbered 5\n[3] >> "));
+    }
+
+    #[test]
+    fn test_edit_files_are_new_and_private() {
+        let first = create_edit_file("let x = 1;").unwrap();
+        let second = create_edit_file("let y = 2;").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(fs::read_to_string(&first).unwrap(), "let x = 1;");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        let _ = fs::remove_file(first);
+        let _ = fs::remove_file(second);
+    }
+
+    #[test]
+    fn test_tab_width() {
+        let mut transcript = Vec::new();
+        let config = Config { tab_width: 8, ..Config::default() };
+        run("\t@\n".as_bytes(), &mut transcript, &config).unwrap();
+
+        // the token and the rendered diagnostic agree on where the '@' is
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.contains("column: 2, display_column: 9,"));
+        assert!(transcript.contains("1 |         @\n  |         ^ "));
+    }
+
+    #[test]
+    fn test_output_modes() {
+        let script = "let x = 5;\nx @ 1\n";
+
+        let mut transcript = Vec::new();
+        let config = Config { echo: true, output: OutputMode::Quiet, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert!(transcript.starts_with("error[E0001]: illegal character '@'\n"));
+        assert!(!transcript.contains("Token") && !transcript.contains(">>"));
+
+        let mut transcript = Vec::new();
+        let config = Config { echo: true, output: OutputMode::Json, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(r#"{"input":1,"result":[{"kind":"Let","literal":"let","line":1,"column":1,"offset":0,"end_offset":3},"#));
+        assert!(lines[0].contains(r#""type":"tokens","stdout":"","diagnostics":[],"elapsed_us":"#));
+        assert!(lines[1].starts_with(r#"{"input":2,"#));
+        assert!(lines[1].contains(r#""diagnostics":[{"code":"E0001","message":"illegal character '@'""#));
+        assert!(lines.iter().all(|line| line.ends_with('}')));
+        assert_eq!("quiet".parse::<OutputMode>(), Ok(OutputMode::Quiet));
+
+        // a failed :edit is reported in the same record shape as any other input
+        let mut transcript = Vec::new();
+        run("let x = 5;\n:edit 3\n:edit x\nx\ny\n".as_bytes(), &mut transcript, &config).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        let lines: Vec<&str> = transcript.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[1].starts_with(r#"{"input":null,"result":[],"type":"error","stdout":"","diagnostics":[{"code":null,"message":"there is no input numbered 3","#));
+        assert!(lines[1].contains(r#""elapsed_us":"#) && lines[1].ends_with('}'));
+        assert!(lines[2].starts_with(r#"{"input":null,"#));
+        // the failed edits don't use up input numbers, and no two records share one
+        assert!(lines[3].starts_with(r#"{"input":2,"#));
+        assert!(lines[4].starts_with(r#"{"input":3,"#));
+    }
+
+    #[test]
+    fn test_unterminated_fixes() {
+        for (source, fixed, help) in [
+            ("x = \"abc  \n", "x = \"abc  \"\n", "= help: close the string: `x = \"abc  \"`"),
+            ("x = \"abc\r\n", "x = \"abc\"\r\n", "= help: close the string: `x = \"abc\"`"),
+            ("/* a /* b\n", "/* a /* b */ */\n", "= help: close the comment: `/* a /* b */ */`"),
+            ("y /* a", "y /* a */", "= help: close the comment: `y /* a */`"),
+        ] {
+            let tokens: Vec<Token> = Lexer::new(source.to_string()).collect();
+            let diagnostic = tokens.iter().flat_map(|tok| diagnose(tok, source)).next().unwrap();
+            let applied = diagnostic.suggestions[0].apply(source);
+            assert_eq!(applied, fixed);
+            // the help shows the whole line the fix is on
+            assert!(diagnostic.render(source, "repl", false, DEFAULT_TAB_WIDTH).ends_with(&format!("{help}\n")));
+            // the fixed source lexes cleanly
+            assert!(Lexer::new(applied).all(|tok| diagnose(&tok, fixed).is_empty()));
+        }
+        // and a string keeps its trailing spaces
+        let tok = Lexer::new(String::from("x = \"abc  \"\n")).nth(2).unwrap();
+        assert_eq!(tok.kind, TokenType::String(Cow::from("abc  ")));
+    }
+
+    #[test]
+    fn test_interpolation_diagnostics() {
+        let script = "\"a${@} ${0b12}\"\n";
+
+        let mut transcript = Vec::new();
+        let config = Config { output: OutputMode::Quiet, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        // the errors inside the string are reported and point into it
+        assert!(transcript.starts_with("error[E0001]: illegal character '@'\n --> repl:1:5\n"));
+        assert!(transcript.contains("error[E0001]: invalid digit '2' for a base 2 literal\n --> repl:1:10\n"));
+
+        let mut transcript = Vec::new();
+        let config = Config { output: OutputMode::Json, ..Config::default() };
+        run(script.as_bytes(), &mut transcript, &config).unwrap();
+        let transcript = String::from_utf8(transcript).unwrap();
+        assert_eq!(transcript.matches(r#"{"code":"E0001""#).count(), 2);
+    }
+}
@@ -0,0 +1,119 @@
+// Utf16Position is a position as the language server protocol counts it: a 0-based line and a
+// 0-based column in UTF-16 code units, so a character outside the BMP such as an emoji counts twice
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Utf16Position {
+    pub line: usize,
+    pub character: usize,
+}
+
+// SourceMap converts between the ways a position in a source can be counted: byte offsets used by
+// tokens and diagnostics, character offsets, and UTF-16 positions used by editors
+pub struct SourceMap {
+    source: String,
+    line_starts: Vec<usize>, // byte offset of the start of every line
+}
+
+impl SourceMap {
+    pub fn new(source: String) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { source, line_starts }
+    }
+
+    /// line_index returns the 0-based line the byte offset is on
+    pub fn line_index(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1,
+        }
+    }
+
+    /// line_text returns the text of a 0-based line without its line break
+    pub fn line_text(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line)?;
+        let end = self.line_starts.get(line + 1).map_or(self.source.len(), |&next| next - 1);
+        Some(self.source[start..end].trim_end_matches('\r'))
+    }
+
+    /// char_offset converts a byte offset into the number of characters before it
+    pub fn char_offset(&self, offset: usize) -> usize {
+        self.source[..self.clamp(offset)].chars().count()
+    }
+
+    /// byte_offset converts a character offset into a byte offset, or None if it is past the end
+    pub fn byte_offset(&self, char_offset: usize) -> Option<usize> {
+        self.source
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(self.source.len()))
+            .nth(char_offset)
+    }
+
+    /// utf16_position converts a byte offset into an editor position
+    pub fn utf16_position(&self, offset: usize) -> Utf16Position {
+        let offset = self.clamp(offset);
+        let line = self.line_index(offset);
+        let character = self.source[self.line_starts[line]..offset].encode_utf16().count();
+        Utf16Position { line, character }
+    }
+
+    /// offset_of_utf16 converts an editor position into a byte offset. It returns None if the line does
+    /// not exist, or if the column is past the end of the line or falls inside a surrogate pair
+    pub fn offset_of_utf16(&self, position: Utf16Position) -> Option<usize> {
+        let line_start = *self.line_starts.get(position.line)?;
+        let text = self.line_text(position.line)?;
+
+        let mut units = 0;
+        for (i, ch) in text.char_indices() {
+            if units == position.character {
+                return Some(line_start + i);
+            }
+            units += ch.len_utf16();
+            if units > position.character {
+                return None;
+            }
+        }
+        (units == position.character).then_some(line_start + text.len())
+    }
+
+    /// clamp moves an offset past the end or inside a character back to the nearest character boundary
+    fn clamp(&self, offset: usize) -> usize {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        offset
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_map_conversions() {
+        // 'é' is 2 bytes and 1 UTF-16 unit, '😀' is 4 bytes and 2 UTF-16 units
+        let map = SourceMap::new(String::from("let a = 1;\r\nlet é😀 = \"x\";\n"));
+        let x = "let a = 1;\r\nlet é😀 = \"".len();
+
+        assert_eq!(map.line_index(0), 0);
+        assert_eq!(map.line_index(12), 1);
+        assert_eq!(map.line_text(0), Some("let a = 1;"));
+        assert_eq!(map.line_text(2), Some(""));
+        assert_eq!(map.line_text(3), None);
+
+        assert_eq!(map.char_offset(x), 22);
+        assert_eq!(map.byte_offset(22), Some(x));
+        assert_eq!(map.byte_offset(100), None);
+
+        assert_eq!(map.utf16_position(x), Utf16Position { line: 1, character: 11 });
+        assert_eq!(map.offset_of_utf16(Utf16Position { line: 1, character: 11 }), Some(x));
+        // a column in the middle of the emoji's surrogate pair
+        assert_eq!(map.offset_of_utf16(Utf16Position { line: 1, character: 6 }), None);
+        // the end of a line is a valid position, one past it is not
+        assert_eq!(map.offset_of_utf16(Utf16Position { line: 0, character: 10 }), Some(10));
+        assert_eq!(map.offset_of_utf16(Utf16Position { line: 0, character: 11 }), None);
+        assert_eq!(map.offset_of_utf16(Utf16Position { line: 5, character: 0 }), None);
+    }
+}
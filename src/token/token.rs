@@ -1,24 +1,58 @@
+//! The tokens the lexer produces and the pieces that go with them. These types grow with the language,
+//! so they are all #[non_exhaustive]: code outside this crate matches the enums with a wildcard arm and
+//! creates the structs with their constructors rather than struct literals.
+//!
+//! Text taken from the input is a Cow: a lexer over a borrowed &str hands out slices of it, while a lexer
+//! that owns its input, such as a streaming one, hands out owned copies.
+
+use std::borrow::Cow;
+
 // Define an enum for different token types
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, PartialEq, Clone)]
-pub enum TokenType {
-    Illegal,
+#[non_exhaustive]
+pub enum TokenType<'a> {
+    Illegal(String), // @, holds a message saying what is wrong, the literal is what was found
     EOF,
     // Identifiers + literals
-    Ident(String), // add, foobar, x, y, ...
-    Int(String),   // 1343456
+    Ident(Cow<'a, str>), // add, foobar, x, y, ...
+    Int(Cow<'a, str>),   // 1343456
+    Float(Cow<'a, str>), // 3.14, 1e9, 2.5e-3
+    String(Cow<'a, str>), // "foo\tbar", holds the value with escape sequences decoded
+    UnterminatedString(String), // "foo<EOF>, a string with no closing quote
+    InterpolatedString(Vec<StringPart<'a>>), // "hello ${name}", the text and embedded expressions in order
+    UnterminatedComment(usize), // /* foo<EOF>, a block comment with no closing */, holds how many levels are still open
     // Operators
     Assign,
+    PlusAssign,
+    MinusAssign,
+    AsteriskAssign,
+    SlashAssign,
+    PercentAssign,
     Plus,
     Bang,
     Minus,
     Slash,
     Asterisk,
+    Power,
+    Percent,
     LT,
     GT,
     Eq,
     NotEq,
     LTE,
     GTE,
+    And,
+    Or,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    Range,          // ..
+    RangeInclusive, // ..=
+    Arrow,          // ->
     // Delimiters
     Comma,
     Semicolon,
@@ -26,6 +60,12 @@ pub enum TokenType {
     RParen,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
+    Colon,
+    Dot,
+    SafeDot,      // ?.
+    SafeLBracket, // ?[
     // Keywords
     Let,
     Function,
@@ -34,30 +74,142 @@ pub enum TokenType {
     Return,
     True,
     False,
+    In,
+    While,
+    For,
+    Break,
+    Continue,
+    Null,
+    Const,
+}
+
+// StringPart is a piece of an interpolated string
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub enum StringPart<'a> {
+    Text(Cow<'a, str>),    // text with escape sequences decoded
+    Expr(Vec<Token<'a>>),  // the tokens between ${ and }, without the braces
 }
 
 // Token represents a token to be parsed
-#[derive(Debug)]
-pub struct Token {
-    pub kind: TokenType,
-    pub literal: String,
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub struct Token<'a> {
+    pub kind: TokenType<'a>,
+    pub literal: Cow<'a, str>,
+    pub line: usize,           // line the token starts on, starting from 1
+    pub column: usize,         // column the token starts at in characters, starting from 1
+    pub display_column: usize, // column the token starts at with tabs expanded, starting from 1
+    pub offset: usize,         // byte offset of the start of the token in the input
+    pub end_offset: usize,     // byte offset just past the end of the token in the input
 }
 
-impl Token {
-    pub fn new<T: ToString>(kind: TokenType, stringer: T) -> Self {
+impl<'a> Token<'a> {
+    pub fn new<T: Into<Cow<'a, str>>>(kind: TokenType<'a>, literal: T) -> Self {
         Self {
             kind,
-            literal: stringer.to_string(),
+            literal: literal.into(),
+            line: 0,
+            column: 0,
+            display_column: 0,
+            offset: 0,
+            end_offset: 0,
         }
     }
 
+    /// span returns the byte range the token covers in the input
+    pub fn span(&self) -> std::ops::Range<usize> {
+        self.offset..self.end_offset
+    }
+
+    /// set_location sets where the token starts in the input
+    pub fn set_location(&mut self, location: Location) {
+        self.line = location.line;
+        self.column = location.column;
+        self.display_column = location.display_column;
+        self.offset = location.offset;
+    }
+
+    /// set_end_offset sets the byte offset just past the end of the token
+    pub fn set_end_offset(&mut self, end_offset: usize) {
+        self.end_offset = end_offset;
+    }
+
     /// set_literal_str sets the literal field in the Token struct
-    pub fn set_literal_str(&mut self, s: String) {
-        self.literal = s;
+    pub fn set_literal_str<T: Into<Cow<'a, str>>>(&mut self, s: T) {
+        self.literal = s.into();
     }
 
     /// set_kind sets the kind field in the Token struct
-    pub fn set_kind(&mut self, t: TokenType) {
+    pub fn set_kind(&mut self, t: TokenType<'a>) {
         self.kind = t;
     }
 }
+
+// TriviaKind tells apart the pieces of input that sit between tokens
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub enum TriviaKind {
+    Whitespace,
+    LineComment,  // from // up to, not including, the end of the line
+    BlockComment, // /* */ including any nested comments
+}
+
+// Trivia is whitespace or a comment the lexer skipped, kept for tools that reproduce the input exactly
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub struct Trivia<'a> {
+    pub kind: TriviaKind,
+    pub text: Cow<'a, str>,
+    pub location: Location, // where the trivia starts in the input
+}
+
+impl<'a> Trivia<'a> {
+    pub fn new<T: Into<Cow<'a, str>>>(kind: TriviaKind, text: T, location: Location) -> Self {
+        Self {
+            kind,
+            text: text.into(),
+            location,
+        }
+    }
+}
+
+// TokenWithTrivia is a token with the trivia in front of it, the EOF token carries the trailing trivia
+#[derive(Debug, PartialEq, Clone)]
+#[non_exhaustive]
+pub struct TokenWithTrivia<'a> {
+    pub leading_trivia: Vec<Trivia<'a>>,
+    pub token: Token<'a>,
+}
+
+impl<'a> TokenWithTrivia<'a> {
+    pub fn new(leading_trivia: Vec<Trivia<'a>>, token: Token<'a>) -> Self {
+        Self { leading_trivia, token }
+    }
+}
+
+// Location is a point in the input as tracked by the lexer
+#[derive(Debug, PartialEq, Clone, Copy)]
+#[non_exhaustive]
+pub struct Location {
+    pub line: usize,           // starting from 1
+    pub column: usize,         // characters from the start of the line, starting from 1
+    pub display_column: usize, // terminal column with tabs expanded to the next tab stop, starting from 1
+    pub offset: usize,         // bytes from the start of the input
+}
+
+impl Location {
+    pub fn new(line: usize, column: usize, display_column: usize, offset: usize) -> Self {
+        Self {
+            line,
+            column,
+            display_column,
+            offset,
+        }
+    }
+
+    /// start returns the location of the first character of an input
+    pub fn start() -> Self {
+        Self::new(1, 1, 1, 0)
+    }
+}
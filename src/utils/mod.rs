@@ -1,9 +1,61 @@
-/// is_letter_or_underscore returns true if ch is an english alphabet or an underscore
+/// DEFAULT_TAB_WIDTH is the number of columns between tab stops unless configured otherwise
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+/// next_tab_stop returns the 1-based display column a tab at display_column moves to
+pub fn next_tab_stop(display_column: usize, tab_width: usize) -> usize {
+    let tab_width = tab_width.max(1);
+    (display_column - 1) / tab_width * tab_width + tab_width + 1
+}
+
+/// expand_tabs replaces every tab in a line with spaces up to the next tab stop, so it can be
+/// printed with carets lined up underneath it
+pub fn expand_tabs(line: &str, tab_width: usize) -> String {
+    let mut out = String::with_capacity(line.len());
+    for ch in line.chars() {
+        match ch {
+            '\t' => {
+                let column = out.chars().count() + 1;
+                out.push_str(&" ".repeat(next_tab_stop(column, tab_width) - column));
+            }
+            _ => out.push(ch),
+        }
+    }
+    out
+}
+
+/// is_letter_or_underscore returns true if ch can start an identifier, i.e. it is an underscore or has the
+/// Unicode XID_Start property. Letters in any script do, combining marks such as vowel signs don't
 pub fn is_letter_or_underscore(ch: char) -> bool {
-    return 'a' <= ch && ch <= 'z' || 'A' <= ch && ch <= 'Z' || ch == '_';
+    unicode_ident::is_xid_start(ch) || ch == '_'
+}
+
+/// is_identifier_char returns true if ch can appear after the first character of an identifier, i.e.
+/// it has the Unicode XID_Continue property. That covers digits and connectors in any script as well
+/// as combining marks such as accents and the vowel signs and viramas of Indic scripts
+pub fn is_identifier_char(ch: char) -> bool {
+    unicode_ident::is_xid_continue(ch)
 }
 
 /// is_digit returns true if ch is a number between 0 and 9 inclusive and false otherwise
 pub fn is_digit(ch: char) -> bool {
-    return '0' <= ch && ch <= '9';
+    ch.is_ascii_digit()
+}
+
+/// json_string quotes s as a JSON string literal, escaping it as needed
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
 }